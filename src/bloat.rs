@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Write;
+use object::{ Object, ObjectSymbolTable };
+use bstr::ByteSlice;
+use argh::FromArgs;
+use crate::common::{ Timings, collect_map, crate_name, load_file, symbol_table };
+
+
+/// Cross-platform Symbol Size Summary by crate
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "bloat")]
+pub struct Options {
+    /// object file
+    #[argh(positional)]
+    file: PathBuf,
+
+    /// only print the top N crates
+    #[argh(option)]
+    n: Option<usize>,
+
+    /// sum outlined function
+    #[argh(switch)]
+    sum_outlined: bool,
+
+    /// use dynamic symbol table
+    #[argh(switch)]
+    dynamic: bool,
+}
+
+impl Options {
+    pub fn exec(self, mmap: bool, mut timings: Timings, stdout: &mut dyn Write) -> anyhow::Result<()> {
+        let buf = load_file(&self.file, mmap)?;
+        let obj = object::File::parse(&*buf)?;
+        timings.phase("load");
+
+        if !obj.has_debug_symbols() {
+            eprintln!("WARN: The file is missing debug symbols.");
+        }
+
+        let map = collect_map(symbol_table(&obj, self.dynamic)?.symbols(), self.sum_outlined, false, None);
+        timings.phase("collect");
+
+        // crate -> (size, count)
+        let mut groups: HashMap<&[u8], (u64, u64)> = HashMap::new();
+        let mut total = 0;
+
+        for (name, &(_, size)) in map.iter() {
+            let entry = groups.entry(crate_name(name)).or_default();
+            entry.0 += size;
+            entry.1 += 1;
+            total += size;
+        }
+
+        let mut output = groups.into_iter().collect::<Vec<_>>();
+        output.sort_by(|(name, (size, _)), (name2, (size2, _))| {
+            size2.cmp(size).then_with(|| name.cmp(name2))
+        });
+
+        let n = self.n.unwrap_or(output.len());
+
+        for (name, (size, count)) in output.iter().take(n) {
+            let percent = if total == 0 {
+                0.0
+            } else {
+                *size as f64 * 100.0 / total as f64
+            };
+
+            writeln!(stdout, "{:.2}%\t{}\t{}\t\t{}", percent, size, count, name.as_bstr())?;
+        }
+
+        if n < output.len() {
+            let (size, count) = output[n..].iter()
+                .fold((0, 0), |(size, count), (_, (s, c))| (size + s, count + c));
+            writeln!(stdout, "...\t{}\t{}\t\t({} more crates)", size, count, output.len() - n)?;
+        }
+
+        writeln!(stdout, "total:\t{}\t{}", total, map.len())?;
+        timings.phase("group");
+
+        Ok(())
+    }
+}
@@ -1,14 +1,22 @@
 use std::rc::Rc;
-use std::collections::HashMap;
+use std::collections::{ HashMap, BTreeSet };
 use bstr::ByteSlice;
 use object::{ Symbol, SymbolKind, ObjectSymbol };
 use rustc_demangle::demangle;
 
 
-pub fn collect_map<'data, T: 'data>(symbols: T, filter_outlined: bool)
+/// Text symbol address and size by demangled name
+///
+/// If `sections` is given, it also records which section each name was first seen in.
+pub fn collect_map<'data, T>(
+    symbols: T,
+    filter_outlined: bool,
+    full_demangle: bool,
+    mut sections: Option<&mut HashMap<Rc<[u8]>, object::SectionIndex>>
+)
     -> HashMap<Rc<[u8]>, (u64, u64)>
 where
-    T: Iterator<Item = Symbol<'data, 'data>>
+    T: Iterator<Item = Symbol<'data, 'data>> + 'data
 {
     let mut map: HashMap<Rc<[u8]>, (u64, u64)> = HashMap::new();
     let outlined_name = Rc::from("OUTLINED_FUNCTION_".as_bytes());
@@ -19,7 +27,7 @@ where
         if let Some(name) = symbol.name()
             .ok()
             .filter(|name| !name.is_empty())
-            .map(|name| format!("{:#}", demangle(name)))
+            .map(|name| Demangle(name, false).render(full_demangle))
             .map(|name| if filter_outlined && name.as_bytes().starts_with_str(&outlined_name) {
                 Rc::clone(&outlined_name)
             } else {
@@ -29,6 +37,10 @@ where
             let addr = symbol.address();
             let size = symbol.size();
 
+            if let (Some(sections), Some(section_idx)) = (sections.as_mut(), symbol.section_index()) {
+                sections.entry(Rc::clone(&name)).or_insert(section_idx);
+            }
+
             map.entry(name)
                 .and_modify(|entry| entry.1 += size)
                 .or_insert_with(|| (addr, size));
@@ -39,6 +51,664 @@ where
     map
 }
 
+/// Text symbol name, address and size, sorted by demangled name
+///
+/// Same output as `collect_map` without the hash map, same-named symbols keep
+/// the first address and add up their sizes.
+pub fn collect_sorted<'data, T>(symbols: T, filter_outlined: bool, full_demangle: bool)
+    -> Vec<(Box<[u8]>, u64, u64)>
+where
+    T: Iterator<Item = Symbol<'data, 'data>> + 'data
+{
+    let outlined_name = "OUTLINED_FUNCTION_";
+
+    let mut list = symbols
+        .filter(|symbol| symbol.kind() == SymbolKind::Text)
+        .filter_map(|symbol| {
+            let name = symbol.name()
+                .ok()
+                .filter(|name| !name.is_empty())
+                .map(|name| Demangle(name, false).render(full_demangle))?;
+            let name = if filter_outlined && name.starts_with(outlined_name) {
+                Box::from(outlined_name.as_bytes())
+            } else {
+                name.into_bytes().into_boxed_slice()
+            };
+
+            Some((name, symbol.address(), symbol.size()))
+        })
+        .collect::<Vec<_>>();
+
+    // stable, so the first of same-named symbols stays first
+    list.sort_by(|(name, ..), (name2, ..)| name.cmp(name2));
+    list.dedup_by(|(name, _, size), (prev, _, prev_size)| if name == prev {
+        *prev_size += *size;
+        true
+    } else {
+        false
+    });
+
+    list.shrink_to_fit();
+    list
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    RustV0,
+    RustLegacy,
+    Itanium,
+    Swift,
+    C
+}
+
+/// Guess mangling scheme by prefix
+pub fn mangling_scheme(name: &str) -> Scheme {
+    fn is_legacy_hash(name: &str) -> bool {
+        // `17h` + 16 hex digits + `E`
+        name.len() > 20
+            && name.ends_with('E')
+            && name[..name.len() - 1].ends_with(|c: char| c.is_ascii_hexdigit())
+            && name.get(name.len() - 20..name.len() - 17) == Some("17h")
+    }
+
+    // Mach-O adds an extra leading underscore
+    let name = match name.strip_prefix('_') {
+        Some(stripped) if stripped.starts_with("_Z")
+            || stripped.starts_with("_R")
+            || stripped.starts_with("$s")
+            || stripped.starts_with("$S")
+            || stripped.starts_with("_T0") => stripped,
+        _ => name
+    };
+
+    if name.starts_with("_R") {
+        Scheme::RustV0
+    } else if name.starts_with("_ZN") && is_legacy_hash(name) {
+        Scheme::RustLegacy
+    } else if name.starts_with("_Z") {
+        Scheme::Itanium
+    } else if name.starts_with("$s") || name.starts_with("$S") || name.starts_with("_T0") {
+        Scheme::Swift
+    } else {
+        Scheme::C
+    }
+}
+
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Scheme::RustV0 => "rust",
+            Scheme::RustLegacy => "rust-legacy",
+            Scheme::Itanium => "c++",
+            Scheme::Swift => "swift",
+            Scheme::C => "c"
+        })
+    }
+}
+
+/// Optional scheme column, empty when not requested
+pub struct SchemeColumn(pub Option<Scheme>);
+
+impl std::fmt::Display for SchemeColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            Some(scheme) => scheme.fmt(f),
+            None => Ok(())
+        }
+    }
+}
+
+/// Context path of a Swift symbol, eg `$s4main5OuterV5inneryyF` to `main.Outer.inner`
+///
+/// This only decodes plain identifiers and nominal type markers, and stops at the
+/// first thing it doesn't understand (types, word substitutions, punycode).
+/// Returns `None` if no identifier could be decoded.
+pub fn demangle_swift(name: &str) -> Option<String> {
+    let name = name.strip_prefix('_').filter(|name| name.starts_with('$')).unwrap_or(name);
+    let mut rest = ["$s", "$S", "_T0"].iter()
+        .find_map(|prefix| name.strip_prefix(prefix))?
+        .as_bytes();
+
+    let mut path = Vec::new();
+
+    loop {
+        match rest.first() {
+            // word substitution or punycode identifier, the path would be incomplete
+            Some(b'0') => return None,
+            Some(b'1'..=b'9') => {
+                let len = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+                let n: usize = std::str::from_utf8(&rest[..len]).ok()?.parse().ok()?;
+                let ident = rest.get(len..len + n)?;
+                path.push(std::str::from_utf8(ident).ok()?);
+                rest = &rest[len + n..];
+            },
+            // class, struct, enum, protocol
+            Some(b'C') | Some(b'V') | Some(b'O') | Some(b'P') if !path.is_empty() => rest = &rest[1..],
+            _ => break
+        }
+    }
+
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.join("."))
+    }
+}
+
+/// Demangled symbol name, also trying Swift if enabled
+///
+/// Falls back to the raw name if Swift demangling fails. `{:#}` hides the Rust hash.
+pub struct Demangle<'a>(pub &'a str, pub bool);
+
+impl std::fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let Demangle(name, swift) = *self;
+
+        if swift && mangling_scheme(name) == Scheme::Swift {
+            return match demangle_swift(name) {
+                Some(name) => f.write_str(&name),
+                None => f.write_str(name)
+            };
+        }
+
+        if f.alternate() {
+            write!(f, "{:#}", demangle(name))
+        } else {
+            write!(f, "{}", demangle(name))
+        }
+    }
+}
+
+impl Demangle<'_> {
+    /// Name without the Rust hash, or with it for `--full-demangle`
+    pub fn render(&self, full: bool) -> String {
+        if full {
+            self.to_string()
+        } else {
+            format!("{:#}", self)
+        }
+    }
+}
+
+/// `.a`, `.rlib` and `.lib` are parsed as archive
+pub fn is_archive_path(path: &std::path::Path) -> bool {
+    matches!(path.extension().and_then(|ext| ext.to_str()), Some("a" | "rlib" | "lib"))
+}
+
+/// Zero padded hex address
+pub struct Address(pub u64, pub usize);
+
+/// `--addr-width` digits, 8 or 16
+#[derive(Clone, Copy, Debug)]
+pub struct AddrWidth(pub usize);
+
+impl std::str::FromStr for AddrWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(AddrWidth(8)),
+            "16" => Ok(AddrWidth(16)),
+            _ => Err(format!("address width must be 8 or 16: {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:01$x}", self.0, self.1)
+    }
+}
+
+/// Quoted and escaped JSON string
+pub struct JsonStr<'a>(pub &'a str);
+
+impl std::fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Lowercase hex string without separator
+pub struct HexStr<'a>(pub &'a [u8]);
+
+impl std::fmt::Display for HexStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for &b in self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// SHA-256, fed incrementally like `flate2::Crc`
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0
+        }
+    }
+}
+
+impl Sha256 {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+    ];
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+
+        while !data.is_empty() {
+            let n = data.len().min(64 - self.block_len);
+            self.block[self.block_len..][..n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+
+        for (&k, &w) in Sha256::K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(k).wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, x) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(x);
+        }
+    }
+}
+
+/// Leading path segment of a demangled name, `[unknown]` if there is none
+///
+/// `<T as Trait>::f` is attributed to the crate of `Trait`.
+pub fn crate_name(name: &[u8]) -> &[u8] {
+    let name = name.trim_start_with(|c| matches!(c, '<' | '&' | '*' | '['));
+    let name = name.strip_prefix(b"dyn ")
+        .or_else(|| name.strip_prefix(b"mut "))
+        .or_else(|| name.strip_prefix(b"const "))
+        .unwrap_or(name);
+
+    let head = match name.find("::") {
+        Some(idx) => &name[..idx],
+        None => return b"[unknown]"
+    };
+
+    if let Some(idx) = head.find(" as ") {
+        return crate_name(&name[idx + 4..]);
+    }
+
+    if !head.is_empty() && head.iter().all(|&b| b.is_ascii_alphanumeric() || b == b'_') {
+        head
+    } else {
+        b"[unknown]"
+    }
+}
+
+/// Static symbol table, falls back to the dynamic one for stripped objects
+pub fn symbol_table<'data, 'file>(obj: &'file object::File<'data>, dynamic: bool)
+    -> anyhow::Result<object::read::SymbolTable<'data, 'file>>
+{
+    use anyhow::Context;
+    use object::Object;
+
+    if dynamic {
+        return obj.dynamic_symbol_table().context("not found dynamic symbol table");
+    }
+
+    match obj.symbol_table() {
+        Some(table) => Ok(table),
+        None => {
+            let table = obj.dynamic_symbol_table().context("not found symbol table")?;
+            eprintln!("WARN: The file is missing symbol table, use dynamic symbol table.");
+            Ok(table)
+        }
+    }
+}
+
+/// ELF symbol version names by symbol index, from `.gnu.version`
+///
+/// The version table follows the dynamic symbols, static symbols get the version
+/// of the global dynamic symbol of the same name. Names that already carry a
+/// version, like `.symver` ones, are left out. Non-ELF objects and objects
+/// without a version table give an empty map.
+pub fn symbol_versions<'data>(obj: &object::File<'data>, dynamic: bool)
+    -> anyhow::Result<HashMap<usize, &'data [u8]>>
+{
+    use object::{ Object, ObjectSymbol };
+    use object::read::elf::{ ElfFile, FileHeader };
+
+    fn versions<'data, Elf>(file: &ElfFile<'data, Elf>, dynamic: bool) -> anyhow::Result<HashMap<usize, &'data [u8]>>
+    where
+        Elf: FileHeader<Endian = object::Endianness>
+    {
+        let endian = file.endian();
+        let sections = file.raw_header().sections(endian, file.data())?;
+        let table = match sections.versions(endian, file.data())? {
+            Some(table) => table,
+            None => return Ok(HashMap::new())
+        };
+
+        let mut map = HashMap::new();
+        let mut names = HashMap::new();
+        for sym in file.dynamic_symbols() {
+            let idx = sym.index().0;
+            if let Some(version) = table.version(table.version_index(endian, idx))? {
+                map.insert(idx, version.name());
+                if sym.is_global() {
+                    if let Ok(name) = sym.name_bytes() {
+                        names.insert(name, version.name());
+                    }
+                }
+            }
+        }
+
+        if dynamic {
+            return Ok(map);
+        }
+
+        Ok(file.symbols()
+            .filter(|sym| sym.is_global())
+            .filter_map(|sym| {
+                let name = sym.name_bytes().ok().filter(|name| !name.contains(&b'@'))?;
+                Some((sym.index().0, *names.get(name)?))
+            })
+            .collect())
+    }
+
+    match obj {
+        object::File::Elf32(file) => versions(file, dynamic),
+        object::File::Elf64(file) => versions(file, dynamic),
+        _ => Ok(HashMap::new())
+    }
+}
+
+/// Switch default from `SYMBOL_TOOLS_<NAME>`, set by `1`, `true` or `yes`
+pub fn env_switch(name: &str) -> bool {
+    std::env::var(format!("SYMBOL_TOOLS_{}", name))
+        .map(|value| matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Option default from `SYMBOL_TOOLS_<NAME>`
+pub fn env_option<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display
+{
+    let key = format!("SYMBOL_TOOLS_{}", name);
+
+    match std::env::var(&key) {
+        Ok(value) => value.parse()
+            .map(Some)
+            .map_err(|err| anyhow::format_err!("bad {}: {}", key, err)),
+        Err(_) => Ok(None)
+    }
+}
+
+/// nm-style type letter, uppercase for global symbols
+pub fn symbol_kind(obj: &object::File<'_>, sym: &Symbol<'_, '_>) -> char {
+    use object::{ Object, ObjectSection, SymbolSection, SectionKind };
+
+    let mut kind = match sym.section() {
+        SymbolSection::Undefined => 'U',
+        SymbolSection::Absolute => 'A',
+        SymbolSection::Common => 'C',
+        SymbolSection::Section(idx) => match obj.section_by_index(idx).map(|section| section.kind()) {
+            Ok(SectionKind::Text) => 't',
+            Ok(SectionKind::Data) | Ok(SectionKind::Tls) | Ok(SectionKind::TlsVariables)
+                | Ok(SectionKind::Elf(_)) => 'd',
+            Ok(SectionKind::ReadOnlyData) | Ok(SectionKind::ReadOnlyString)
+                | Ok(SectionKind::Note) => 'r',
+            Ok(SectionKind::UninitializedData) | Ok(SectionKind::UninitializedTls) => 'b',
+            Ok(SectionKind::Common) => 'C',
+            _ => '?',
+        },
+        _ => '?',
+    };
+
+    if sym.is_global() {
+        kind = kind.to_ascii_uppercase();
+    }
+
+    kind
+}
+
+/// Demangled text symbols from `nm -f bsd` output
+///
+/// Lines without an address (undefined symbols) and the `file.o:` headers
+/// nm prints for each archive member are accepted. Weak symbols are only
+/// kept when `include_weak` is set.
+pub fn parse_nm_bsd<R: std::io::BufRead>(
+    mut reader: R,
+    include_weak: bool,
+    swift: bool,
+    full_demangle: bool
+)
+    -> anyhow::Result<BTreeSet<Vec<u8>>>
+{
+    use bstr::io::BufReadExt;
+
+    fn next_field(line: &[u8]) -> (&[u8], &[u8]) {
+        let line = line.trim_start();
+        match line.find_byteset(b" \t") {
+            Some(idx) => (&line[..idx], line[idx..].trim_start()),
+            None => (line, &[])
+        }
+    }
+
+    let mut output = BTreeSet::new();
+
+    reader.for_byte_line(|line| {
+        let line = line.trim();
+
+        // member header, eg `libfoo.a(bar.o):` or `bar.o:`
+        if line.is_empty() || (line.ends_with(b":") && next_field(line).1.is_empty()) {
+            return Ok(true);
+        }
+
+        // address column is blank for undefined symbols
+        let (first, rest) = next_field(line);
+        let (kind, name) = if first.len() == 1 {
+            (first, rest)
+        } else {
+            next_field(rest)
+        };
+
+        // nm letters: `t`/`T` local/global text, `W`/`V` weak defined text/object,
+        // `w`/`v` are weak references and provide nothing
+        match kind {
+            b"t" | b"T" => (),
+            b"W" | b"V" if include_weak => (),
+            _ => return Ok(true)
+        }
+
+        if !name.is_empty() {
+            let name = name.to_str_lossy();
+            output.insert(Demangle(&name, swift).render(full_demangle).into_bytes());
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(output)
+}
+
+/// Phase timer for `--timings`, reports to stderr
+pub struct Timings {
+    enabled: bool,
+    start: std::time::Instant
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Timings {
+        Timings { enabled, start: std::time::Instant::now() }
+    }
+
+    /// Report the time since the previous phase
+    pub fn phase(&mut self, name: &str) {
+        if self.enabled {
+            eprintln!("timing: {}:\t{:.3?}", name, self.start.elapsed());
+        }
+
+        self.start = std::time::Instant::now();
+    }
+}
+
+pub enum FileData {
+    Mmap(memmap2::Mmap),
+    Owned(Vec<u8>)
+}
+
+impl std::ops::Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Mmap(mmap) => mmap.as_ref(),
+            FileData::Owned(buf) => buf.as_slice()
+        }
+    }
+}
+
+/// Open a file, naming it in the error
+pub fn open_file(path: &std::path::Path) -> anyhow::Result<std::fs::File> {
+    use anyhow::Context;
+
+    std::fs::File::open(path).with_context(|| {
+        // the file itself may not exist, so try to resolve its directory instead
+        let path = std::fs::canonicalize(path)
+            .ok()
+            .or_else(|| {
+                let dir = path.parent()
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .unwrap_or_else(|| std::path::Path::new("."));
+                let dir = std::fs::canonicalize(dir).ok()?;
+                Some(dir.join(path.file_name()?))
+            })
+            .unwrap_or_else(|| path.to_owned());
+        format!("opening {}", path.display())
+    })
+}
+
+/// Map file, or read it into memory when `mmap` is disabled
+///
+/// Every subcommand goes through `memmap2` here. Empty files are rejected
+/// up front instead of failing later in the object parser.
+pub fn load_file(path: &std::path::Path, mmap: bool) -> anyhow::Result<FileData> {
+    let fd = open_file(path)?;
+
+    if fd.metadata()?.len() == 0 {
+        anyhow::bail!("empty input file: {}", path.display());
+    }
+
+    if mmap {
+        let mmap = unsafe { memmap2::Mmap::map(&fd)? };
+        Ok(FileData::Mmap(mmap))
+    } else {
+        use std::io::Read;
+
+        let mut buf = Vec::new();
+        (&fd).read_to_end(&mut buf)?;
+        Ok(FileData::Owned(buf))
+    }
+}
+
+/// Read a whole file into memory, rejecting empty files like `load_file`
+pub fn read_file(path: &std::path::Path) -> anyhow::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut buf = Vec::new();
+    open_file(path)?.read_to_end(&mut buf)?;
+
+    if buf.is_empty() {
+        anyhow::bail!("empty input file: {}", path.display());
+    }
+
+    Ok(buf)
+}
+
 pub trait IteratorExt: Iterator {
     fn flat_result<U, T, E>(self) -> FlatResultIter<Self, U, T, E>
     where
@@ -125,13 +795,33 @@ impl<'a, 'b, T: ?Sized> AsRef<T> for DoubleLife<'a, 'b, T> {
     }
 }
 
-pub fn data_range<'data>(
-    data: &'data [u8],
+/// Section data, decompressed if needed
+///
+/// zlib and zstd are supported through `object`'s `compression` feature,
+/// other formats are an error instead of compressed bytes.
+pub fn section_data<'data>(section: &object::Section<'data, '_>)
+    -> anyhow::Result<std::borrow::Cow<'data, [u8]>>
+{
+    use anyhow::Context;
+    use object::{ CompressionFormat, ObjectSection };
+
+    let name = section.name().unwrap_or("<error>");
+
+    if section.compressed_file_range()?.format == CompressionFormat::Unknown {
+        anyhow::bail!("section {} uses an unsupported compression format", name);
+    }
+
+    section.uncompressed_data()
+        .with_context(|| format!("failed to decompress section {}", name))
+}
+
+pub fn data_range(
+    data: &[u8],
     data_address: u64,
     range_address: u64,
     size: u64
 )
-    -> anyhow::Result<&'data [u8]>
+    -> anyhow::Result<&[u8]>
 {
     use std::convert::TryInto;
     use anyhow::Context;
@@ -149,6 +839,7 @@ pub fn print_pretty_bytes(
     stdout: &mut dyn std::io::Write,
     base: u64,
     bytes: &[u8],
+    addr_width: usize
 ) -> anyhow::Result<()> {
     use std::fmt;
 
@@ -187,15 +878,13 @@ pub fn print_pretty_bytes(
         }
     }
 
-    let addr = base as *const u8;
-
     for (offset, chunk) in bytes.chunks(16).enumerate() {
-        let addr = addr.wrapping_add(offset * 16);
+        let addr = base.wrapping_add(offset as u64 * 16);
 
         writeln!(
             stdout,
-            "{:018p}: {} {}",
-            addr,
+            "0x{}: {} {}",
+            Address(addr, addr_width),
             HexPrinter(chunk),
             AsciiPrinter(chunk)
         )?;
@@ -203,3 +892,47 @@ pub fn print_pretty_bytes(
 
     Ok(())
 }
+
+pub enum DiffOp {
+    Equal(usize),
+    Delete(usize),
+    Insert(usize)
+}
+
+/// Longest common subsequence diff, index based
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffOp> {
+    let (n, m) = (old.len(), new.len());
+    let mut table = vec![0usize; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[at(i, j)] = if old[i] == new[j] {
+                table[at(i + 1, j + 1)] + 1
+            } else {
+                table[at(i + 1, j)].max(table[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(DiffOp::Equal(i));
+            i += 1;
+            j += 1;
+        } else if table[at(i + 1, j)] >= table[at(i, j + 1)] {
+            ops.push(DiffOp::Delete(i));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(j));
+            j += 1;
+        }
+    }
+
+    ops.extend((i..n).map(DiffOp::Delete));
+    ops.extend((j..m).map(DiffOp::Insert));
+    ops
+}
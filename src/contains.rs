@@ -1,77 +1,124 @@
-use std::fs;
-use std::path::PathBuf;
-use std::collections::BTreeSet;
-use std::io::{ self, Write, BufReader };
-use anyhow::Context;
+use std::path::{ Path, PathBuf };
+use std::borrow::Cow;
+use std::collections::{ BTreeMap, BTreeSet };
+use std::io::{ Write, BufReader };
 use bstr::ByteSlice;
-use bstr::io::BufReadExt;
-use memmap2::Mmap;
 use object::{ Object, ObjectSymbolTable, ObjectSymbol };
-use rustc_demangle::demangle;
+use object::read::archive::ArchiveFile;
 use argh::FromArgs;
+use crate::common::{
+    AddrWidth, Address, Demangle, Timings,
+    env_option, env_switch, is_archive_path, load_file, open_file, parse_nm_bsd, symbol_table
+};
 
 
 /// Cross-platform Symbol Finder
 #[derive(FromArgs, Debug)]
 #[argh(subcommand, name = "contains")]
 pub struct Options {
-    /// archive file
+    /// archive files (.a/.rlib/.lib) or `nm -f bsd` outputs, followed by object file
     #[argh(positional)]
-    ar: PathBuf,
+    files: Vec<PathBuf>,
 
-    /// object file
-    #[argh(positional)]
-    obj: PathBuf,
+    /// count weak definitions in archives and nm outputs (nm `W`/`V`) as provided
+    #[argh(switch)]
+    include_weak: bool,
+
+    /// use dynamic symbol table
+    #[argh(switch)]
+    dynamic: bool,
+
+    /// sort by size
+    #[argh(switch)]
+    sort: bool,
+
+    /// sort by `size` or `name`
+    #[argh(option)]
+    sort_by: Option<SortBy>,
+
+    /// address digits, 8 or 16 (default)
+    #[argh(option)]
+    addr_width: Option<AddrWidth>,
+
+    /// demangle swift symbols
+    #[argh(switch)]
+    swift: bool,
+
+    /// keep the Rust hash in demangled names, to tell apart same-named instances
+    #[argh(switch)]
+    full_demangle: bool,
+
+    /// only print how many archive symbols the object provides
+    #[argh(switch)]
+    count_only: bool,
+
+    /// also print archive symbols the object doesn't provide
+    #[argh(switch)]
+    output_missing: bool,
+
+    /// end with a `SUMMARY key=value ...` line instead of the total line
+    #[argh(switch)]
+    porcelain: bool,
 }
 
-impl Options {
-    pub fn exec(self) -> anyhow::Result<()> {
-        let afd = fs::File::open(&self.ar)?;
-        let ofd = fs::File::open(&self.obj)?;
+#[derive(Clone, Copy, Debug)]
+enum SortBy {
+    Size,
+    Name
+}
 
-        let mut areader = BufReader::new(afd);
-        let omap = unsafe { Mmap::map(&ofd)? };
-        let oobj = object::File::parse(omap.as_ref())?;
+impl std::str::FromStr for SortBy {
+    type Err = String;
 
-        if !oobj.has_debug_symbols() {
-            eprintln!("WARN: The new file is missing debug symbols.");
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(SortBy::Size),
+            "name" => Ok(SortBy::Name),
+            _ => Err(format!("unknown sort key: {}", s))
         }
+    }
+}
+
+impl Options {
+    /// Fill options that weren't given and turn on switches from `SYMBOL_TOOLS_*` environment variables
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.sort |= env_switch("SORT");
+        self.swift |= env_switch("SWIFT");
+        self.addr_width = self.addr_width.or(env_option("ADDR_WIDTH")?);
 
-        let mut input = BTreeSet::new();
+        Ok(())
+    }
 
-        // llvm-nm -f bsd ./<your ar>
-        areader.for_byte_line(|line| {
-            let line = line.trim();
+    pub fn exec(self, mmap: bool, mut timings: Timings, stdout: &mut dyn Write) -> anyhow::Result<()> {
+        let (obj, ars) = match self.files.split_last() {
+            Some((obj, ars)) if !ars.is_empty() => (obj, ars),
+            _ => anyhow::bail!("need archive and object file")
+        };
 
-            if line.is_empty() || line.starts_with_str("../") {
-                return Ok(true);
-            }
+        let addr_width = self.addr_width.map_or(16, |width| width.0);
 
-            let mut words = line.words();
-            let _ = words.next(); // ignore address
+        let obuf = load_file(obj, mmap)?;
+        let oobj = object::File::parse(&*obuf)?;
 
-            let kind = words.next(); // text kind
-            match kind {
-                Some("t") | Some("T") => (),
-                _ => return Ok(true)
-            }
+        if !oobj.has_debug_symbols() {
+            eprintln!("WARN: The new file is missing debug symbols.");
+        }
 
-            // symbol name
-            if let Some(name) = words.next() {
-                input.insert(format!("{:#}", demangle(name)).into_bytes());
-            }
+        // name -> providers
+        let mut input: BTreeMap<Vec<u8>, BTreeSet<String>> = BTreeMap::new();
 
-            Ok(true)
-        })?;
+        for ar in ars {
+            read_archive(ar, &self, mmap, &mut input)?;
+        }
+        timings.phase("load");
 
         let mut count = 0;
         let mut namebuf = Vec::new();
 
-        let stdout = io::stdout();
-        let mut stdout = stdout.lock();
-
-        let symbol_table = oobj.symbol_table()
-            .context("not found symbol_table")?;
+        let symbol_table = symbol_table(&oobj, self.dynamic)?;
+        let sort_by = self.sort_by.or(if self.sort { Some(SortBy::Size) } else { None });
+        let mut output = Vec::new();
+        let mut found = BTreeSet::new();
 
         for symbol in symbol_table.symbols() {
             if symbol.kind() != object::SymbolKind::Text {
@@ -80,24 +127,155 @@ impl Options {
 
             if let Some(mangled_name) = symbol.name().ok().filter(|name| !name.is_empty()) {
                 namebuf.clear();
-                write!(&mut namebuf, "{:#}", demangle(mangled_name))?;
+                namebuf.extend_from_slice(Demangle(mangled_name, self.swift).render(self.full_demangle).as_bytes());
                 let name = namebuf.as_bytes();
 
-                if !input.contains(name) {
+                let providers = match input.get(name) {
+                    Some(providers) => providers,
+                    None => continue
+                };
+
+                found.insert(name.to_vec());
+
+                if self.count_only {
                     continue
                 }
+                let providers = if ars.len() > 1 {
+                    let providers = providers.iter()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    Cow::Owned(format!("\t{}", providers))
+                } else {
+                    Cow::Borrowed("")
+                };
 
                 let addr = symbol.address();
                 let size = symbol.size();
 
+                if sort_by.is_some() {
+                    output.push((addr, size, name.to_vec(), providers));
+                    continue
+                }
+
+                count += size;
+
+                writeln!(stdout, "{}\t{}\t\t{}{}",
+                    Address(addr, addr_width),
+                    size,
+                    name.as_bstr(),
+                    providers
+                )?;
+            }
+        }
+
+        if let Some(sort_by) = sort_by {
+            match sort_by {
+                SortBy::Size => output.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.2.cmp(&b.2))),
+                SortBy::Name => output.sort_by(|a, b| a.2.cmp(&b.2))
+            }
+
+            for (addr, size, name, providers) in output {
                 count += size;
 
-                writeln!(&mut stdout, "{:018p}\t{}\t\t{}", addr as *const (), size, name.as_bstr())?;
+                writeln!(stdout, "{}\t{}\t\t{}{}",
+                    Address(addr, addr_width),
+                    size,
+                    name.as_bstr(),
+                    providers
+                )?;
             }
         }
 
-        writeln!(&mut stdout, "total:\t\t\t{}", count)?;
+        if self.output_missing {
+            for (name, providers) in input.iter() {
+                if found.contains(name) {
+                    continue
+                }
+
+                if ars.len() > 1 {
+                    let providers = providers.iter()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    writeln!(stdout, "missing:\t\t\t{}\t{}", name.as_bstr(), providers)?;
+                } else {
+                    writeln!(stdout, "missing:\t\t\t{}", name.as_bstr())?;
+                }
+            }
+        }
+
+        if self.porcelain {
+            writeln!(stdout, "SUMMARY count={} total={} archive={}", found.len(), count, input.len())?;
+        } else if self.count_only {
+            let percent = if input.is_empty() {
+                0.0
+            } else {
+                found.len() as f64 * 100.0 / input.len() as f64
+            };
+
+            writeln!(stdout, "found {} of {} ({:.2}%)", found.len(), input.len(), percent)?;
+        } else {
+            writeln!(stdout, "total:\t\t\t{}", count)?;
+        }
+        timings.phase("scan");
 
         Ok(())
     }
 }
+
+fn read_archive(
+    path: &Path,
+    options: &Options,
+    mmap: bool,
+    input: &mut BTreeMap<Vec<u8>, BTreeSet<String>>
+)
+    -> anyhow::Result<()>
+{
+    let ar_name = path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+
+    if is_archive_path(path) {
+        let abuf = load_file(path, mmap)?;
+        let archive = ArchiveFile::parse(&*abuf)?;
+
+        for member in archive.members() {
+            let member = member?;
+            let data = member.data(&*abuf)?;
+            let provider = format!("{}({})", ar_name, member.name().as_bstr());
+
+            // skip import descriptors and metadata members
+            let file = match object::File::parse(data) {
+                Ok(file) => file,
+                Err(_) => continue
+            };
+
+            for symbol in file.symbols() {
+                if symbol.kind() != object::SymbolKind::Text || !symbol.is_definition() {
+                    continue
+                }
+
+                if symbol.is_weak() && !options.include_weak {
+                    continue
+                }
+
+                if let Some(name) = symbol.name().ok().filter(|name| !name.is_empty()) {
+                    input.entry(Demangle(name, options.swift).render(options.full_demangle).into_bytes())
+                        .or_default()
+                        .insert(provider.clone());
+                }
+            }
+        }
+    } else {
+        let areader = BufReader::new(open_file(path)?);
+
+        for name in parse_nm_bsd(areader, options.include_weak, options.swift, options.full_demangle)? {
+            input.entry(name)
+                .or_default()
+                .insert(ar_name.to_string());
+        }
+    }
+
+    Ok(())
+}
@@ -1,14 +1,16 @@
-use std::fs;
 use std::rc::Rc;
+use std::borrow::Cow;
 use std::path::PathBuf;
-use std::collections::HashMap;
-use std::io::{ self, Write };
+use std::collections::{ HashMap, BTreeMap };
+use std::io::Write;
 use anyhow::Context;
-use memmap2::Mmap;
-use object::{ Object, ObjectSymbolTable };
+use object::{ Object, ObjectSection, ObjectSymbol, ObjectSymbolTable, SectionIndex, SymbolKind };
 use bstr::ByteSlice;
 use argh::FromArgs;
-use crate::common::collect_map;
+use crate::common::{
+    AddrWidth, Address, Demangle, HexStr, Sha256, Timings,
+    collect_map, collect_sorted, crate_name, data_range, env_option, env_switch, load_file, section_data
+};
 
 
 /// Cross-platform Symbol Differ
@@ -19,9 +21,9 @@ pub struct Options {
     #[argh(positional)]
     old: PathBuf,
 
-    /// new object file
+    /// new object file, defaults to the old one with `--within`
     #[argh(positional)]
-    new: PathBuf,
+    new: Option<PathBuf>,
 
     /// oneway diff
     #[argh(switch)]
@@ -33,30 +35,208 @@ pub struct Options {
 
     /// sum outlined function
     #[argh(switch)]
-    sum_outlined: bool
+    sum_outlined: bool,
+
+    /// fold outlined functions into one summary row instead of a row each
+    #[argh(switch)]
+    tag_outlined: bool,
+
+    /// diff symbol count by crate instead of size
+    #[argh(switch)]
+    by_count: bool,
+
+    /// also print symbols whose address changed but size didn't
+    #[argh(switch)]
+    show_moves: bool,
+
+    /// print symbols that moved to a different section instead of size
+    #[argh(switch)]
+    section_moves: bool,
+
+    /// diff symbols against their counterparts named with this suffix, eg `.cold`
+    #[argh(option)]
+    within: Option<String>,
+
+    /// keep the Rust hash in demangled names, to tell apart same-named instances
+    #[argh(switch)]
+    full_demangle: bool,
+
+    /// compare each symbol's bytes instead of its size, slower
+    #[argh(switch)]
+    by_content: bool,
+
+    /// print symbols present in both with a different size, combines with
+    /// `--added-only` and `--removed-only`
+    #[argh(switch)]
+    changed_only: bool,
+
+    /// print symbols only present in the new file
+    #[argh(switch)]
+    added_only: bool,
+
+    /// print symbols only present in the old file
+    #[argh(switch)]
+    removed_only: bool,
+
+    /// compare the sizes of DWARF `.debug_*` sections by name instead of symbols
+    #[argh(switch)]
+    debug: bool,
+
+    /// end with a `SUMMARY key=value ...` line instead of the total lines
+    #[argh(switch)]
+    porcelain: bool,
+
+    /// sort both symbol tables by name and merge them instead of building hash maps,
+    /// O(n log n) instead of O(n) but less memory; unsorted rows come out in name order
+    #[argh(switch)]
+    low_memory: bool,
+
+    /// only print symbols whose old and new size are on opposite sides of N bytes
+    #[argh(option)]
+    crosses: Option<u64>,
+
+    /// print the delta column as a percent of the old symbol size or the old total: old, total
+    #[argh(option)]
+    relative_to: Option<RelativeTo>,
+
+    /// fail if total size grows more than this percent
+    #[argh(option)]
+    fail_over_percent: Option<f64>,
+
+    /// address digits, 8 or 16 (default)
+    #[argh(option)]
+    addr_width: Option<AddrWidth>,
 }
 
-pub struct Differ<'a>(&'a HashMap<Rc<[u8]>, (u64, u64)>, &'a HashMap<Rc<[u8]>, (u64, u64)>, bool);
+#[derive(Clone, Copy, Debug)]
+enum RelativeTo {
+    Old,
+    Total
+}
+
+impl std::str::FromStr for RelativeTo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "old" => Ok(RelativeTo::Old),
+            "total" => Ok(RelativeTo::Total),
+            _ => Err(format!("unknown relative base: {}", s))
+        }
+    }
+}
+
+/// Delta column, in bytes or as a percent of the base size
+struct Delta(i64, Option<u64>);
+
+impl std::fmt::Display for Delta {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let percent = match *self {
+            Delta(diff, None) => return write!(f, "{}", diff),
+            Delta(diff, Some(base)) if base != 0 => diff as f64 / base as f64 * 100.0,
+            Delta(0, Some(_)) => 0.0,
+            Delta(diff, Some(_)) => f64::INFINITY.copysign(diff as f64)
+        };
+
+        write!(f, "{:.2}%", percent)
+    }
+}
+
+const OUTLINED_NAME: &str = "OUTLINED_FUNCTION_";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Change {
+    Changed,
+    /// same size at a different address, with `--show-moves`
+    Moved,
+    Added,
+    Removed
+}
+
+type DiffRow<'a> = dyn FnMut(&[u8], (u64, i64), (u64, i64), Change) -> anyhow::Result<()> + 'a;
+
+pub struct Differ<'a>(
+    &'a HashMap<Rc<[u8]>, (u64, u64)>,
+    &'a HashMap<Rc<[u8]>, (u64, u64)>,
+    bool,
+    bool
+);
 
 impl Differ<'_> {
+    /// Calls `f` with name, old, new and the kind of change
     fn for_each<F>(&self, mut f: F) -> anyhow::Result<()>
     where
-        F: FnMut(&Rc<[u8]>, (u64, i64), (u64, i64)) -> anyhow::Result<()>
+        F: FnMut(&[u8], (u64, i64), (u64, i64), Change) -> anyhow::Result<()>
     {
         for (name, &(addr, size)) in self.0.iter() {
             if let Some(&(new_addr, new_size)) = self.1.get(name) {
-                if size != new_size {
-                    f(name, (addr, size as i64), (new_addr, new_size as i64))?;
+                if size != new_size || (self.3 && addr != new_addr) {
+                    f(name, (addr, size as i64), (new_addr, new_size as i64), Change::of(size, new_size))?;
                 }
             } else {
-                f(name, (addr, size as i64), (0, 0))?;
+                f(name, (addr, size as i64), (0, 0), Change::Removed)?;
             }
         }
 
         if self.2 {
             for (name, &(new_addr, new_size)) in self.1.iter() {
                 if !self.0.contains_key(name) {
-                    f(name, (0, 0), (new_addr, new_size as i64))?;
+                    f(name, (0, 0), (new_addr, new_size as i64), Change::Added)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Change {
+    fn of(old_size: u64, new_size: u64) -> Change {
+        if old_size == new_size { Change::Moved } else { Change::Changed }
+    }
+}
+
+/// `Differ` over name sorted lists, a single merge-join pass in name order
+pub struct MergeDiffer<'a>(
+    &'a [(Box<[u8]>, u64, u64)],
+    &'a [(Box<[u8]>, u64, u64)],
+    bool,
+    bool
+);
+
+impl MergeDiffer<'_> {
+    fn for_each<F>(&self, mut f: F) -> anyhow::Result<()>
+    where
+        F: FnMut(&[u8], (u64, i64), (u64, i64), Change) -> anyhow::Result<()>
+    {
+        use std::cmp::Ordering;
+
+        let mut old = self.0.iter().peekable();
+        let mut new = self.1.iter().peekable();
+
+        loop {
+            let order = match (old.peek(), new.peek()) {
+                (Some((name, ..)), Some((new_name, ..))) => name.cmp(new_name),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break
+            };
+
+            match order {
+                Ordering::Less => if let Some((name, addr, size)) = old.next() {
+                    f(name, (*addr, *size as i64), (0, 0), Change::Removed)?;
+                },
+                Ordering::Greater => if let Some((name, new_addr, new_size)) = new.next() {
+                    if self.2 {
+                        f(name, (0, 0), (*new_addr, *new_size as i64), Change::Added)?;
+                    }
+                },
+                Ordering::Equal => if let (Some((name, addr, size)), Some((_, new_addr, new_size)))
+                    = (old.next(), new.next())
+                {
+                    if size != new_size || (self.3 && addr != new_addr) {
+                        f(name, (*addr, *size as i64), (*new_addr, *new_size as i64), Change::of(*size, *new_size))?;
+                    }
                 }
             }
         }
@@ -66,14 +246,62 @@ impl Differ<'_> {
 }
 
 impl Options {
-    pub fn exec(self) -> anyhow::Result<()> {
-        let old_fd = fs::File::open(&self.old)?;
-        let new_fd = fs::File::open(&self.new)?;
+    /// Fill options that weren't given and turn on switches from `SYMBOL_TOOLS_*` environment variables
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.sort |= env_switch("SORT");
+        self.addr_width = self.addr_width.or(env_option("ADDR_WIDTH")?);
+
+        Ok(())
+    }
+
+    pub fn exec(self, mmap: bool, mut timings: Timings, stdout: &mut dyn Write) -> anyhow::Result<()> {
+        let new_path = match (&self.new, &self.within) {
+            (Some(path), _) => path,
+            (None, Some(_)) => &self.old,
+            (None, None) => anyhow::bail!("need new object file")
+        };
+
+        if self.within.is_some() && self.section_moves {
+            anyhow::bail!("--within can't be used with --section-moves");
+        }
+
+        if self.by_content && (self.section_moves || self.by_count || self.within.is_some()) {
+            anyhow::bail!("--by-content can't be used with --section-moves, --by-count or --within");
+        }
 
-        let old_mmap = unsafe { Mmap::map(&old_fd)? };
-        let old_obj = object::File::parse(old_mmap.as_ref())?;
-        let new_mmap = unsafe { Mmap::map(&new_fd)? };
-        let new_obj = object::File::parse(new_mmap.as_ref())?;
+        if self.tag_outlined && (self.section_moves || self.by_count || self.by_content) {
+            anyhow::bail!("--tag-outlined can't be used with --section-moves, --by-count or --by-content");
+        }
+
+        if (self.changed_only || self.added_only || self.removed_only)
+            && (self.section_moves || self.by_count || self.debug)
+        {
+            anyhow::bail!("--changed-only, --added-only and --removed-only can't be used with --section-moves, --by-count or --debug");
+        }
+
+        if self.crosses.is_some() && (self.section_moves || self.by_count || self.by_content || self.debug) {
+            anyhow::bail!("--crosses can't be used with --section-moves, --by-count, --by-content or --debug");
+        }
+
+        if self.relative_to.is_some() && (self.section_moves || self.by_count || self.by_content || self.debug) {
+            anyhow::bail!("--relative-to can't be used with --section-moves, --by-count, --by-content or --debug");
+        }
+
+        if self.debug && (self.section_moves || self.by_count || self.by_content || self.within.is_some()
+            || self.tag_outlined || self.low_memory)
+        {
+            anyhow::bail!("--debug can't be used with other diff modes");
+        }
+
+        if self.low_memory && (self.section_moves || self.by_count || self.by_content || self.within.is_some()) {
+            anyhow::bail!("--low-memory can't be used with --section-moves, --by-count, --by-content or --within");
+        }
+
+        let old_buf = load_file(&self.old, mmap)?;
+        let old_obj = object::File::parse(&*old_buf)?;
+        let new_buf = load_file(new_path, mmap)?;
+        let new_obj = object::File::parse(&*new_buf)?;
+        timings.phase("load");
 
         if !old_obj.has_debug_symbols() {
             eprintln!("WARN: The old file is missing debug symbols.");
@@ -83,69 +311,561 @@ impl Options {
             eprintln!("WARN: The new file is missing debug symbols.");
         }
 
+        if self.by_content {
+            let old_map = content_map(&old_obj, self.sum_outlined, self.full_demangle)?;
+            let new_map = content_map(&new_obj, self.sum_outlined, self.full_demangle)?;
+            timings.phase("collect");
+
+            content_diff(stdout, &old_map, &new_map, !self.oneway, self.porcelain, |change| self.shows(change))?;
+            timings.phase("diff");
+            return Ok(());
+        }
+
+        if self.debug {
+            let old_sections = debug_sections(&old_obj)?;
+            let new_sections = debug_sections(&new_obj)?;
+            timings.phase("collect");
+
+            debug_diff(stdout, &old_sections, &new_sections, self.sort, self.porcelain)?;
+            timings.phase("diff");
+            return Ok(());
+        }
+
+        if self.low_memory {
+            let mut old_list = collect_sorted(
+                old_obj.symbol_table().context("no found symbol table")?.symbols(),
+                self.sum_outlined || self.tag_outlined,
+                self.full_demangle
+            );
+            let mut new_list = collect_sorted(
+                new_obj.symbol_table().context("no found symbol table")?.symbols(),
+                self.sum_outlined || self.tag_outlined,
+                self.full_demangle
+            );
+
+            let outlined = if self.tag_outlined {
+                let take = |list: &mut Vec<(Box<[u8]>, u64, u64)>| list
+                    .binary_search_by(|(name, ..)| (**name).cmp(OUTLINED_NAME.as_bytes()))
+                    .map(|idx| list.remove(idx).2)
+                    .unwrap_or(0);
+                Some((take(&mut old_list), take(&mut new_list)))
+            } else {
+                None
+            };
+            timings.phase("collect");
+
+            let totals = (
+                old_list.iter().map(|&(_, _, size)| size).sum(),
+                new_list.iter().map(|&(_, _, size)| size).sum()
+            );
+            let differ = MergeDiffer(&old_list, &new_list, !self.oneway, self.show_moves);
+            return self.print_diff(stdout, &mut |f| differ.for_each(f), totals, outlined, timings);
+        }
+
+        let mut old_sections = HashMap::new();
+        let mut new_sections = HashMap::new();
+
         let old_map = collect_map(
             old_obj.symbol_table().context("no found symbol table")?.symbols(),
-            self.sum_outlined
+            self.sum_outlined || self.tag_outlined,
+            self.full_demangle,
+            Some(&mut old_sections).filter(|_| self.section_moves)
         );
         let new_map = collect_map(
             new_obj.symbol_table().context("no found symbol table")?.symbols(),
-            self.sum_outlined
+            self.sum_outlined || self.tag_outlined,
+            self.full_demangle,
+            Some(&mut new_sections).filter(|_| self.section_moves)
+        );
+
+        let (mut old_map, mut new_map) = match self.within.as_ref() {
+            Some(suffix) => split_suffix(old_map, new_map, suffix.as_bytes()),
+            None => (old_map, new_map)
+        };
+
+        // folded by `collect_map`, set aside for a summary row
+        let outlined = if self.tag_outlined {
+            let name = OUTLINED_NAME.as_bytes();
+            let old_size = old_map.remove(name).map(|(_, size)| size).unwrap_or(0);
+            let new_size = new_map.remove(name).map(|(_, size)| size).unwrap_or(0);
+            Some((old_size, new_size))
+        } else {
+            None
+        };
+        timings.phase("collect");
+
+        if self.section_moves {
+            section_moves(stdout, (&old_obj, &old_sections), (&new_obj, &new_sections), self.porcelain)?;
+            timings.phase("diff");
+            return Ok(());
+        }
+
+        if self.by_count {
+            count_by_crate(stdout, &old_map, &new_map, self.sort, self.porcelain)?;
+            timings.phase("diff");
+            return Ok(());
+        }
+
+        let totals = (
+            old_map.values().map(|&(_, size)| size).sum(),
+            new_map.values().map(|&(_, size)| size).sum()
         );
+        let differ = Differ(&old_map, &new_map, !self.oneway, self.show_moves);
+        self.print_diff(stdout, &mut |f| differ.for_each(f), totals, outlined, timings)
+    }
+
+    /// Whether `--changed-only`, `--added-only` and `--removed-only` let this row through
+    fn shows(&self, change: Change) -> bool {
+        match change {
+            _ if !(self.changed_only || self.added_only || self.removed_only) => true,
+            Change::Changed | Change::Moved => self.changed_only,
+            Change::Added => self.added_only,
+            Change::Removed => self.removed_only
+        }
+    }
 
-        let stdout = io::stdout();
-        let mut stdout = stdout.lock();
+    /// Whether `--crosses` lets a size change through
+    fn crosses(&self, old_size: i64, new_size: i64) -> bool {
+        match self.crosses {
+            Some(n) => (old_size as u64 >= n) != (new_size as u64 >= n),
+            None => true
+        }
+    }
+
+    /// Rows, outlined row and totals of a size diff
+    ///
+    /// `totals` are the old and new sizes without outlined functions.
+    fn print_diff(
+        &self,
+        stdout: &mut dyn Write,
+        for_each: &mut dyn FnMut(&mut DiffRow<'_>) -> anyhow::Result<()>,
+        totals: (u64, u64),
+        outlined: Option<(u64, u64)>,
+        mut timings: Timings
+    )
+        -> anyhow::Result<()>
+    {
+        let addr_width = self.addr_width.map_or(16, |width| width.0);
+        let old_total = totals.0 + outlined.map(|(old_size, _)| old_size).unwrap_or(0);
+        let delta = |diff_size: i64, old_size: u64| Delta(diff_size, match self.relative_to {
+            Some(RelativeTo::Old) => Some(old_size),
+            Some(RelativeTo::Total) => Some(old_total),
+            None => None
+        });
 
         let mut change_count = 0;
+        let mut move_count = 0;
+        let mut rows = 0;
 
         if !self.sort {
-            Differ(&old_map, &new_map, !self.oneway)
-                .for_each(|name, (old_addr, old_size), (new_addr, new_size)| {
-                    let diff_size = new_size - old_size;
-
-                    change_count += diff_size;
-
-                    writeln!(&mut stdout,
-                        "{:018p}\t{:018p}\t{}\t{}\t{}\t\t{}",
-                        old_addr as *const (),
-                        new_addr as *const (),
-                        old_size,
-                        new_size,
-                        diff_size,
-                        name.as_bstr()
-                    )?;
-
-                    Ok(())
-                })?;
+            for_each(&mut |name, (old_addr, old_size), (new_addr, new_size), change| {
+                if !self.shows(change) || !self.crosses(old_size, new_size) {
+                    return Ok(());
+                }
+
+                let moved = change == Change::Moved;
+                let diff_size = new_size - old_size;
+
+                change_count += diff_size;
+                move_count += moved as usize;
+                rows += 1;
+
+                writeln!(stdout,
+                    "{}\t{}\t{}\t{}\t{}\t\t{}{}",
+                    Address(old_addr, addr_width),
+                    Address(new_addr, addr_width),
+                    old_size,
+                    new_size,
+                    delta(diff_size, old_size as u64),
+                    name.as_bstr(),
+                    if moved { "\t(moved)" } else { "" }
+                )?;
+
+                Ok(())
+            })?;
         } else {
             let mut output = Vec::new();
 
-            Differ(&old_map, &new_map, !self.oneway)
-                .for_each(|name, old, new| {
-                    output.push((name.clone(), old, new));
-                    Ok(())
-                })?;
+            for_each(&mut |name, old, new, change| {
+                if self.shows(change) && self.crosses(old.1, new.1) {
+                    output.push((Box::<[u8]>::from(name), old, new, change == Change::Moved));
+                }
+                Ok(())
+            })?;
 
-            output.sort_unstable_by_key(|(_, (_, old_size), (_, new_size))| new_size - old_size);
+            // name breaks ties, rows may come from a hash map
+            output.sort_unstable_by(|(name, (_, old_size), (_, new_size), _), (name2, (_, old_size2), (_, new_size2), _)| {
+                (new_size - old_size).cmp(&(new_size2 - old_size2)).then_with(|| name.cmp(name2))
+            });
 
-            for (name, (old_addr, old_size), (new_addr, new_size)) in output {
+            for (name, (old_addr, old_size), (new_addr, new_size), moved) in output {
                 let diff_size = new_size - old_size;
 
                 change_count += diff_size;
+                move_count += moved as usize;
+                rows += 1;
 
-                writeln!(&mut stdout,
-                    "{:018p}\t{:018p}\t{}\t{}\t{}\t\t{}",
-                    old_addr as *const (),
-                    new_addr as *const (),
+                writeln!(stdout,
+                    "{}\t{}\t{}\t{}\t{}\t\t{}{}",
+                    Address(old_addr, addr_width),
+                    Address(new_addr, addr_width),
                     old_size,
                     new_size,
-                    diff_size,
-                    name.as_bstr()
+                    delta(diff_size, old_size as u64),
+                    name.as_bstr(),
+                    if moved { "\t(moved)" } else { "" }
                 )?;
             }
         }
 
-        writeln!(&mut stdout, "total:\t\t\t{}", change_count)?;
+        if let Some((old_size, new_size)) = outlined {
+            let diff_size = new_size as i64 - old_size as i64;
+            change_count += diff_size;
+
+            writeln!(stdout, "outlined:\t\t{}\t{}\t{}", old_size, new_size, delta(diff_size, old_size))?;
+        }
+
+        let growth = self.fail_over_percent.map(|_| {
+            let (old_outlined, new_outlined) = outlined.unwrap_or((0, 0));
+            let old_total = totals.0 + old_outlined;
+            let new_total = totals.1 + new_outlined;
+            let percent = if old_total != 0 {
+                (new_total as f64 - old_total as f64) / old_total as f64 * 100.0
+            } else if new_total != 0 {
+                f64::INFINITY
+            } else {
+                0.0
+            };
+
+            (old_total, new_total, percent)
+        });
+
+        if self.porcelain {
+            write!(stdout, "SUMMARY count={} total={}", rows, change_count)?;
+            if self.show_moves {
+                write!(stdout, " moves={}", move_count)?;
+            }
+            if let Some((old_total, new_total, percent)) = growth {
+                write!(stdout, " old_total={} new_total={} growth={:.2}", old_total, new_total, percent)?;
+            }
+            writeln!(stdout)?;
+        } else {
+            writeln!(stdout, "total:\t\t\t{}", change_count)?;
+
+            if self.show_moves {
+                writeln!(stdout, "moves:\t\t\t{}", move_count)?;
+            }
+
+            if let Some((old_total, new_total, percent)) = growth {
+                writeln!(stdout, "old total:\t\t\t{}", old_total)?;
+                writeln!(stdout, "new total:\t\t\t{}", new_total)?;
+                writeln!(stdout, "growth:\t\t\t{:.2}%", percent)?;
+            }
+        }
+        timings.phase("diff");
+
+        if let (Some(limit), Some((_, _, percent))) = (self.fail_over_percent, growth) {
+            if percent > limit {
+                anyhow::bail!("total size grows {:.2}%, over {}%", percent, limit);
+            }
+        }
 
         Ok(())
     }
 }
+
+/// Text symbol bytes by demangled name
+///
+/// Symbols that share a name, such as outlined functions with `--sum-outlined`,
+/// are concatenated in symbol table order.
+fn content_map(obj: &object::File<'_>, filter_outlined: bool, full_demangle: bool)
+    -> anyhow::Result<HashMap<Rc<[u8]>, Vec<u8>>>
+{
+    use std::collections::hash_map::Entry;
+
+    let mut map: HashMap<Rc<[u8]>, Vec<u8>> = HashMap::new();
+    let mut sections: HashMap<SectionIndex, (u64, Cow<'_, [u8]>)> = HashMap::new();
+
+    for symbol in obj.symbol_table().context("no found symbol table")?.symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text)
+    {
+        let name = match symbol.name().ok().filter(|name| !name.is_empty()) {
+            Some(name) => Demangle(name, false).render(full_demangle),
+            None => continue
+        };
+        let name = if filter_outlined && name.starts_with(OUTLINED_NAME) {
+            OUTLINED_NAME
+        } else {
+            name.as_str()
+        };
+
+        let section_idx = match symbol.section_index() {
+            Some(idx) => idx,
+            None => continue
+        };
+
+        let (section_addr, data) = match sections.entry(section_idx) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let section = obj.section_by_index(section_idx)?;
+                entry.insert((section.address(), section_data(&section)?))
+            }
+        };
+
+        // bss-like sections have no bytes, treat their symbols as empty
+        let data = data_range(data, *section_addr, symbol.address(), symbol.size())
+            .unwrap_or_default();
+
+        map.entry(Rc::from(name.as_bytes()))
+            .or_default()
+            .extend_from_slice(data);
+    }
+
+    Ok(map)
+}
+
+fn content_diff(
+    stdout: &mut dyn Write,
+    old_map: &HashMap<Rc<[u8]>, Vec<u8>>,
+    new_map: &HashMap<Rc<[u8]>, Vec<u8>>,
+    twoway: bool,
+    porcelain: bool,
+    shows: impl Fn(Change) -> bool
+)
+    -> anyhow::Result<()>
+{
+    let mut output = Vec::new();
+
+    // bytes are compared as is, digests are only printed
+    for (name, old_bytes) in old_map.iter() {
+        match new_map.get(name) {
+            Some(new_bytes) if old_bytes != new_bytes =>
+                output.push((name, Some(old_bytes), Some(new_bytes))),
+            Some(_) => (),
+            None => output.push((name, Some(old_bytes), None))
+        }
+    }
+
+    if twoway {
+        for (name, new_bytes) in new_map.iter() {
+            if !old_map.contains_key(name) {
+                output.push((name, None, Some(new_bytes)));
+            }
+        }
+    }
+
+    output.retain(|(_, old_bytes, new_bytes)| shows(match (old_bytes, new_bytes) {
+        (Some(_), Some(_)) => Change::Changed,
+        (Some(_), None) => Change::Removed,
+        _ => Change::Added
+    }));
+    output.sort_by(|a, b| a.0.cmp(b.0));
+
+    let (mut changed, mut added, mut removed) = (0, 0, 0);
+    let hash = |bytes: Option<&Vec<u8>>| match bytes {
+        Some(bytes) => {
+            let mut hasher = Sha256::default();
+            hasher.update(bytes);
+            HexStr(&hasher.finish()).to_string()
+        },
+        None => "-".repeat(64)
+    };
+
+    for &(name, old_bytes, new_bytes) in output.iter() {
+        let status = match (old_bytes, new_bytes) {
+            (Some(_), Some(_)) => { changed += 1; "changed" },
+            (Some(_), None) => { removed += 1; "removed" },
+            _ => { added += 1; "added" }
+        };
+
+        writeln!(stdout, "{}\t{}\t{}\t\t{}", hash(old_bytes), hash(new_bytes), status, name.as_bstr())?;
+    }
+
+    if porcelain {
+        writeln!(stdout, "SUMMARY changed={} added={} removed={}", changed, added, removed)?;
+    } else {
+        writeln!(stdout, "changed:\t{}\tadded:\t{}\tremoved:\t{}", changed, added, removed)?;
+    }
+
+    Ok(())
+}
+
+fn count_by_crate(
+    stdout: &mut dyn Write,
+    old_map: &HashMap<Rc<[u8]>, (u64, u64)>,
+    new_map: &HashMap<Rc<[u8]>, (u64, u64)>,
+    sort: bool,
+    porcelain: bool
+)
+    -> anyhow::Result<()>
+{
+    // crate -> (added, removed, net)
+    let mut groups: BTreeMap<&[u8], (i64, i64, i64)> = BTreeMap::new();
+
+    for name in old_map.keys() {
+        let entry = groups.entry(crate_name(name)).or_default();
+        entry.2 -= 1;
+        if !new_map.contains_key(name) {
+            entry.1 += 1;
+        }
+    }
+
+    for name in new_map.keys() {
+        let entry = groups.entry(crate_name(name)).or_default();
+        entry.2 += 1;
+        if !old_map.contains_key(name) {
+            entry.0 += 1;
+        }
+    }
+
+    let mut output = groups.into_iter()
+        .filter(|(_, (added, removed, net))| *added != 0 || *removed != 0 || *net != 0)
+        .collect::<Vec<_>>();
+
+    if sort {
+        output.sort_by_key(|(_, (_, _, net))| *net);
+    }
+
+    let mut total = (0, 0, 0);
+
+    for (name, (added, removed, net)) in output {
+        total.0 += added;
+        total.1 += removed;
+        total.2 += net;
+
+        writeln!(stdout, "+{}\t-{}\t{}\t\t{}", added, removed, net, name.as_bstr())?;
+    }
+
+    if porcelain {
+        writeln!(stdout, "SUMMARY added={} removed={} net={}", total.0, total.1, total.2)?;
+    } else {
+        writeln!(stdout, "total:\t+{}\t-{}\t{}", total.0, total.1, total.2)?;
+    }
+
+    Ok(())
+}
+
+type SymbolMap = HashMap<Rc<[u8]>, (u64, u64)>;
+
+/// Key transform for `--within`
+///
+/// New side keeps suffixed names with the suffix stripped, old side keeps the
+/// unsuffixed names that have such a counterpart.
+fn split_suffix(old_map: SymbolMap, new_map: SymbolMap, suffix: &[u8]) -> (SymbolMap, SymbolMap)
+{
+    let new_map = new_map.into_iter()
+        .filter_map(|(name, value)| name.strip_suffix(suffix)
+            .filter(|base| !base.is_empty())
+            .map(|base| (Rc::from(base), value))
+        )
+        .collect::<HashMap<_, _>>();
+    let old_map = old_map.into_iter()
+        .filter(|(name, _)| !name.ends_with(suffix) && new_map.contains_key(name))
+        .collect();
+
+    (old_map, new_map)
+}
+
+fn section_moves(
+    stdout: &mut dyn Write,
+    old: (&object::File<'_>, &HashMap<Rc<[u8]>, SectionIndex>),
+    new: (&object::File<'_>, &HashMap<Rc<[u8]>, SectionIndex>),
+    porcelain: bool
+)
+    -> anyhow::Result<()>
+{
+    let section_name = |obj: &object::File<'_>, idx| obj.section_by_index(idx)
+        .ok()
+        .and_then(|section| section.name().ok().map(str::to_owned))
+        .unwrap_or_else(|| String::from("<error>"));
+
+    let mut output = Vec::new();
+
+    for (name, &old_idx) in old.1.iter() {
+        if let Some(&new_idx) = new.1.get(name) {
+            let old_section = section_name(old.0, old_idx);
+            let new_section = section_name(new.0, new_idx);
+
+            if old_section != new_section {
+                output.push((name, old_section, new_section));
+            }
+        }
+    }
+
+    output.sort();
+
+    for (name, old_section, new_section) in output.iter() {
+        writeln!(stdout, "{} \u{2192} {}\t\t{}", old_section, new_section, name.as_bstr())?;
+    }
+
+    if porcelain {
+        writeln!(stdout, "SUMMARY moved={}", output.len())?;
+    } else {
+        writeln!(stdout, "moved:\t\t{}", output.len())?;
+    }
+
+    Ok(())
+}
+
+/// Sizes of DWARF sections by name, as stored in the file
+///
+/// ELF `.debug_*` and `.zdebug_*`, Mach-O `__debug_*`. Same-named sections add up.
+fn debug_sections(obj: &object::File<'_>) -> anyhow::Result<BTreeMap<String, u64>> {
+    let mut map = BTreeMap::new();
+
+    for section in obj.sections() {
+        let name = section.name()?;
+
+        if [".debug_", ".zdebug_", "__debug_", "__zdebug_"].iter().any(|prefix| name.starts_with(prefix)) {
+            *map.entry(name.to_owned()).or_default() += section.size();
+        }
+    }
+
+    Ok(map)
+}
+
+fn debug_diff(
+    stdout: &mut dyn Write,
+    old_map: &BTreeMap<String, u64>,
+    new_map: &BTreeMap<String, u64>,
+    sort: bool,
+    porcelain: bool
+)
+    -> anyhow::Result<()>
+{
+    let mut output = old_map.keys()
+        .chain(new_map.keys().filter(|name| !old_map.contains_key(*name)))
+        .map(|name| {
+            let old_size = old_map.get(name).copied().unwrap_or(0);
+            let new_size = new_map.get(name).copied().unwrap_or(0);
+            (name, old_size, new_size)
+        })
+        .filter(|(_, old_size, new_size)| old_size != new_size)
+        .collect::<Vec<_>>();
+
+    if sort {
+        output.sort_by(|(name, old_size, new_size), (name2, old_size2, new_size2)| {
+            (*new_size as i64 - *old_size as i64).cmp(&(*new_size2 as i64 - *old_size2 as i64))
+                .then_with(|| name.cmp(name2))
+        });
+    } else {
+        output.sort();
+    }
+
+    for (name, old_size, new_size) in output.iter() {
+        writeln!(stdout, "{}\t{}\t{}\t\t{}", old_size, new_size, *new_size as i64 - *old_size as i64, name)?;
+    }
+
+    let old_total: u64 = old_map.values().sum();
+    let new_total: u64 = new_map.values().sum();
+    let change = new_total as i64 - old_total as i64;
+
+    if porcelain {
+        writeln!(stdout, "SUMMARY count={} old_total={} new_total={} total={}",
+            output.len(), old_total, new_total, change)?;
+    } else {
+        writeln!(stdout, "debug total:\t{}\t{}\t{}", old_total, new_total, change)?;
+    }
+
+    Ok(())
+}
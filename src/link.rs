@@ -3,62 +3,782 @@
 use std::fs;
 use std::io::Write;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{ Path, PathBuf };
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::convert::TryInto;
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU64, Ordering };
 use aho_corasick::AhoCorasick;
 use anyhow::Context;
 use argh::FromArgs;
 use bstr::ByteSlice;
-use rustc_demangle::demangle;
-use object::{ Object, ObjectSection, ObjectSymbol };
+use object::{ Object, ObjectSection, ObjectSymbol, ObjectSymbolTable };
 use object::read::archive::ArchiveFile;
 use object::read::File;
-use memmap2::Mmap;
 use indexmap::IndexMap;
-use crate::common::{ IteratorExt, DoubleLife, data_range, print_pretty_bytes };
+use crate::common::{
+    IteratorExt, DoubleLife, DiffOp, Demangle, HexStr, JsonStr,
+    data_range, diff_lines, section_data, is_archive_path, load_file, read_file, print_pretty_bytes,
+    symbol_kind, symbol_versions, env_switch, env_option, AddrWidth, FileData, Sha256, Timings
+};
 
 
 /// Cross-platform Symbol Explorer
 #[derive(FromArgs, Debug)]
 #[argh(subcommand, name = "link")]
 pub struct Options {
-    /// object files
+    /// object files, `@file` reads more paths from a response file
     #[argh(positional)]
     objects: Vec<PathBuf>,
+
+    /// read object and archive members from a tar or tar.gz file
+    #[argh(option)]
+    from_archive: Vec<PathBuf>,
+
+    /// symbol index cache file, defaults to one in $XDG_CACHE_HOME/sym or ~/.cache/sym
+    #[argh(option)]
+    cache: Option<PathBuf>,
+
+    /// don't read or write symbol index cache
+    #[argh(switch)]
+    no_cache: bool,
+
+    /// force disassembly arch: x86_64, aarch64, arm, thumb, riscv64
+    #[argh(option)]
+    arch: Option<DisasmArch>,
+
+    /// print not demangle symbol
+    #[argh(switch)]
+    no_demangle: bool,
+
+    /// demangle swift symbols
+    #[argh(switch)]
+    swift: bool,
+
+    /// don't print loaded objects on startup
+    #[argh(switch)]
+    quiet: bool,
+
+    /// expand `*` and `?` in object paths
+    #[argh(switch)]
+    glob: bool,
+
+    /// print each object's architecture, format and endianness, then exit
+    #[argh(switch)]
+    inspect: bool,
+
+    /// treat inputs as flat code blobs without symbols, or Intel HEX for `.hex` files,
+    /// needs `--arch`
+    #[argh(switch)]
+    raw_binary: bool,
+
+    /// load address of `--raw-binary` blobs in hex, default 0, Intel HEX has its own
+    #[argh(option, from_str_fn(parse_hex))]
+    base: Option<u64>,
+
+    /// warn instead of failing on mixed architectures or formats,
+    /// disassembly then only works in objects of a supported arch
+    #[argh(switch)]
+    allow_mixed: bool,
+
+    /// colorize disassembly: auto (default), always, never
+    #[argh(option, default = "ColorChoice::Auto")]
+    color: ColorChoice,
+
+    /// address digits in hexdumps, 8 or 16 (default)
+    #[argh(option)]
+    addr_width: Option<AddrWidth>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum DisasmArch {
+    X86_64,
+    Aarch64,
+    Arm,
+    Thumb,
+    Riscv64
+}
+
+impl std::str::FromStr for DisasmArch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64" => Ok(DisasmArch::X86_64),
+            "aarch64" => Ok(DisasmArch::Aarch64),
+            "arm" => Ok(DisasmArch::Arm),
+            "thumb" => Ok(DisasmArch::Thumb),
+            "riscv64" => Ok(DisasmArch::Riscv64),
+            _ => Err(format!("unsupport arch: {}", s))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice: {}", s))
+        }
+    }
+}
+
+fn parse_hex(value: &str) -> Result<u64, String> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|err| format!("bad hex number {}: {}", value, err))
 }
 
 impl Options {
-    pub fn exec(self) -> anyhow::Result<()> {
-        let fds = self.objects.iter()
-            .map(|path| fs::File::open(path))
-            .collect::<Result<Vec<_>, _>>()?;
-        let bufs = fds.iter()
-            .map(|fd| unsafe { Mmap::map(fd) })
-            .collect::<Result<Vec<_>, _>>()?;
-        let list = self.objects.iter()
+    /// Fill options that weren't given and turn on switches from `SYMBOL_TOOLS_*` environment variables
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.no_demangle |= env_switch("NO_DEMANGLE");
+        self.swift |= env_switch("SWIFT");
+        self.addr_width = self.addr_width.or(env_option("ADDR_WIDTH")?);
+
+        Ok(())
+    }
+
+    pub fn exec(self, mmap: bool, mut timings: Timings) -> anyhow::Result<()> {
+        let mut objects = Vec::new();
+        for path in self.objects.iter() {
+            expand_response_file(path, &mut objects, 0)?;
+        }
+
+        if self.glob {
+            let mut expanded = Vec::new();
+            for path in objects.iter() {
+                expanded.extend(expand_glob(path)?);
+            }
+            objects = expanded;
+        }
+
+        if self.raw_binary && !self.from_archive.is_empty() {
+            anyhow::bail!("--raw-binary can't be used with --from-archive");
+        }
+
+        if self.base.is_some() && !self.raw_binary {
+            anyhow::bail!("--base needs --raw-binary");
+        }
+
+        let mut bufs = if self.raw_binary {
+            let arch = self.arch.context("--raw-binary needs --arch")?;
+            objects.iter()
+                .map(|path| read_raw_binary(path, arch, self.base).map(FileData::Owned))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            objects.iter()
+                .map(|path| load_file(path, mmap))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let mut names = objects.clone();
+        for path in self.from_archive.iter() {
+            for (name, buf) in read_tar(path)? {
+                names.push(name);
+                bufs.push(FileData::Owned(buf));
+            }
+        }
+
+        let list = names.iter()
             .zip(bufs.iter())
-            .map(|(path, buf)| match path.extension() {
-                Some(ext) if ext == OsStr::new("a") || ext == OsStr::new("rlib") =>
-                    ArchiveFile::parse(buf.as_ref())
-                        .map(|ar| Archive::Ar(buf, ar))
-                        .map_err(Into::into),
-                Some(ext) if ext == OsStr::new("o") => {
-                    let name = path.file_name().context("bad file name")?;
-                    // TODO use as_os_str_bytes
-                    let name = name.to_str().context("not utf8 name")?;
-                    let name = Cow::Owned(name.as_bytes().into());
-                    File::parse(buf.as_ref())
-                        .map(|file| Archive::Object(ObjectFile { name, file }) )
-                        .map_err(Into::into)
-                },
-                ext => anyhow::bail!("no support extension: {:?}", ext)
-            })
+            .map(|(path, buf)| open_object(path, buf, self.raw_binary))
             .flat_result()
             .collect::<Result<Vec<_>, anyhow::Error>>()?;
 
-        explorer(&list)
+        timings.phase("load");
+
+        if self.inspect {
+            for obj in list.iter() {
+                println!("{}\t{:?}\t{:?}\t{:?}",
+                    obj.name.as_bstr(),
+                    obj.file.architecture(),
+                    obj.file.format(),
+                    obj.file.endianness()
+                );
+            }
+
+            return Ok(());
+        }
+
+        let cache = if !self.no_cache {
+            let mut stamp_paths = objects;
+            stamp_paths.extend(self.from_archive.iter().cloned());
+            IndexCache::new(&stamp_paths, self.cache.clone())?
+        } else {
+            None
+        };
+
+        explorer(list, cache.as_ref(), &self, mmap, timings)
+    }
+}
+
+const OBJECT_EXTENSIONS: &[&str] = &["o", "so", "dylib", "dll", "exe"];
+
+fn is_object_path(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| OBJECT_EXTENSIONS.iter().any(|e| ext == OsStr::new(e)))
+        .unwrap_or(false)
+}
+
+/// Parse an object or archive, by file extension
+///
+/// `raw` buffers come from `read_raw_binary` and are objects whatever the extension.
+fn open_object<'buf>(path: &Path, buf: &'buf [u8], raw: bool) -> anyhow::Result<Archive<'buf>> {
+    Ok(match path.extension() {
+        _ if !raw && is_archive_path(path) => Archive::Ar(buf, ArchiveFile::parse(buf)?),
+        _ if raw || is_object_path(path) => {
+            let name = path.file_name().context("bad file name")?;
+            let name = Cow::Owned(name.as_encoded_bytes().into());
+            let file = File::parse(buf)?;
+            Archive::Object(Box::new(ObjectFile { name, file }))
+        },
+        ext => anyhow::bail!("no support extension: {:?}", ext)
+    })
+}
+
+/// Wrap a flat code blob or an Intel HEX file in an ELF executable
+///
+/// The ELF has a `.text` section at the load address and no symbols, so the rest
+/// of the explorer can treat it like any other object. Intel HEX files get one
+/// section per contiguous run of records, `.text`, `.text.1` and so on.
+pub fn read_raw_binary(path: &Path, arch: DisasmArch, base: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let data = read_file(path)?;
+
+    let is_ihex = path.extension()
+        .map(|ext| ext == OsStr::new("hex") || ext == OsStr::new("ihex"))
+        .unwrap_or(false);
+
+    let runs = if is_ihex {
+        if base.is_some() {
+            anyhow::bail!("--base can't be used with Intel HEX, it has its own addresses: {}", path.display());
+        }
+
+        parse_ihex(&data).with_context(|| format!("bad Intel HEX file: {}", path.display()))?
+    } else {
+        vec![(base.unwrap_or(0), data)]
+    };
+    let base = match runs.first() {
+        Some((base, _)) => *base,
+        None => anyhow::bail!("no data records: {}", path.display())
+    };
+
+    /// ELF header fields in the class and byte order of the arch
+    struct ElfWriter {
+        buf: Vec<u8>,
+        is_64: bool,
+        endian: object::Endianness
+    }
+
+    impl ElfWriter {
+        fn u16(&mut self, n: u16) {
+            match self.endian {
+                object::Endianness::Little => self.buf.extend_from_slice(&n.to_le_bytes()),
+                object::Endianness::Big => self.buf.extend_from_slice(&n.to_be_bytes())
+            }
+        }
+
+        fn u32(&mut self, n: u32) {
+            match self.endian {
+                object::Endianness::Little => self.buf.extend_from_slice(&n.to_le_bytes()),
+                object::Endianness::Big => self.buf.extend_from_slice(&n.to_be_bytes())
+            }
+        }
+
+        /// Address or offset, `n` is checked to fit ELF32 by the caller
+        fn word(&mut self, n: u64) {
+            match (self.is_64, self.endian) {
+                (true, object::Endianness::Little) => self.buf.extend_from_slice(&n.to_le_bytes()),
+                (true, object::Endianness::Big) => self.buf.extend_from_slice(&n.to_be_bytes()),
+                (false, _) => self.u32(n as u32)
+            }
+        }
+    }
+
+    // every supported arch is little endian, 32-bit Arm marks a Thumb entry with the low bit
+    let (machine, is_64, entry): (u16, bool, u64) = match arch {
+        DisasmArch::X86_64 => (62, true, base),
+        DisasmArch::Aarch64 => (183, true, base),
+        DisasmArch::Arm => (40, false, base),
+        DisasmArch::Thumb => (40, false, base | 1),
+        DisasmArch::Riscv64 => (243, true, base)
+    };
+    let endian = object::Endianness::Little;
+
+    let (ehdr_size, phdr_size, shdr_size) = if is_64 { (64, 56, 64) } else { (52, 32, 40) };
+    let mut shstrtab = b"\0.shstrtab\0".to_vec();
+    let mut text_names = Vec::with_capacity(runs.len());
+    for idx in 0..runs.len() {
+        text_names.push(shstrtab.len() as u32);
+        match idx {
+            0 => shstrtab.extend_from_slice(b".text\0"),
+            idx => shstrtab.extend_from_slice(format!(".text.{}\0", idx).as_bytes())
+        }
+    }
+
+    let section_count: u16 = (runs.len() + 2).try_into()
+        .ok()
+        .filter(|&n| n < 0xff00)
+        .with_context(|| format!("too many sections, {} runs of records: {}", runs.len(), path.display()))?;
+    let text_offset: u64 = ehdr_size;
+    let shstrtab_offset = text_offset + runs.iter().map(|(_, data)| data.len() as u64).sum::<u64>();
+    let shdr_offset = (shstrtab_offset + shstrtab.len() as u64 + 7) & !7;
+
+    if !is_64 {
+        for (addr, data) in runs.iter() {
+            let end = addr.checked_add(data.len() as u64);
+            if end.map(|end| end > 1 << 32).unwrap_or(true) {
+                anyhow::bail!("{:?} is 32-bit, {:#x} bytes at {:#x} don't fit: {}",
+                    arch, data.len(), addr, path.display());
+            }
+        }
+
+        if shdr_offset > u32::MAX.into() {
+            anyhow::bail!("{:?} is 32-bit, the file is too large: {}", arch, path.display());
+        }
+    }
+
+    let mut elf = ElfWriter {
+        buf: Vec::with_capacity(shdr_offset as usize + usize::from(section_count) * shdr_size as usize),
+        is_64,
+        endian
+    };
+
+    // class, byte order, current version
+    elf.buf.extend_from_slice(b"\x7fELF");
+    elf.buf.push(if is_64 { 2 } else { 1 });
+    elf.buf.push(match endian {
+        object::Endianness::Little => 1,
+        object::Endianness::Big => 2
+    });
+    elf.buf.push(1);
+    elf.buf.resize(16, 0);
+    elf.u16(2); // ET_EXEC
+    elf.u16(machine);
+    elf.u32(1);
+    elf.word(entry);
+    elf.word(0); // no program headers
+    elf.word(shdr_offset);
+    elf.u32(0);
+    // the string table is the last section
+    for n in [ehdr_size as u16, phdr_size, 0, shdr_size as u16, section_count, section_count - 1] {
+        elf.u16(n);
+    }
+
+    for (_, data) in runs.iter() {
+        elf.buf.extend_from_slice(data);
+    }
+    elf.buf.extend_from_slice(&shstrtab);
+    elf.buf.resize(shdr_offset as usize, 0);
+
+    let mut section = |name: u32, kind: u32, flags: u64, addr: u64, offset: u64, size: u64| {
+        elf.u32(name);
+        elf.u32(kind);
+        for n in [flags, addr, offset, size] {
+            elf.word(n);
+        }
+        elf.u32(0); // link
+        elf.u32(0); // info
+        elf.word(1); // align
+        elf.word(0); // entsize
+    };
+
+    section(0, 0, 0, 0, 0, 0);
+    let mut offset = text_offset;
+    for ((addr, data), name) in runs.iter().zip(text_names) {
+        // SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR
+        section(name, 1, 0x6, *addr, offset, data.len() as u64);
+        offset += data.len() as u64;
+    }
+    // SHT_STRTAB
+    section(1, 3, 0, 0, shstrtab_offset, shstrtab.len() as u64);
+
+    Ok(elf.buf)
+}
+
+/// Contiguous runs of bytes in an Intel HEX file, by load address
+///
+/// Gaps between records of up to `MAX_FILL` bytes are filled with `0xff`, like
+/// erased flash, farther records start a new run.
+pub fn parse_ihex(text: &[u8]) -> anyhow::Result<Vec<(u64, Vec<u8>)>> {
+    const MAX_FILL: u64 = 64 * 1024;
+
+    let mut chunks = Vec::new();
+    let mut upper = 0;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+
+        let record = line.strip_prefix(b":")
+            .filter(|hex| hex.len() % 2 == 0 && hex.len() >= 10)
+            .and_then(|hex| hex.chunks(2)
+                .map(|b| std::str::from_utf8(b).ok().and_then(|b| u8::from_str_radix(b, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+            )
+            .with_context(|| format!("bad record at line {}", n + 1))?;
+
+        let len = record[0] as usize;
+        if record.len() != len + 5 {
+            anyhow::bail!("bad record length at line {}", n + 1);
+        }
+        if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            anyhow::bail!("bad checksum at line {}", n + 1);
+        }
+
+        let offset = u64::from(u16::from_be_bytes([record[1], record[2]]));
+        let payload = &record[4..][..len];
+
+        match record[3] {
+            0x00 => chunks.push((upper + offset, payload.to_vec())),
+            0x01 => break,
+            0x02 | 0x04 if len != 2 =>
+                anyhow::bail!("bad extended address record length {} at line {}", len, n + 1),
+            0x02 => upper = u64::from(u16::from_be_bytes([payload[0], payload[1]])) << 4,
+            0x04 => upper = u64::from(u16::from_be_bytes([payload[0], payload[1]])) << 16,
+            // start addresses
+            0x03 | 0x05 if len != 4 =>
+                anyhow::bail!("bad start address record length {} at line {}", len, n + 1),
+            0x03 | 0x05 => (),
+            kind => anyhow::bail!("unsupported record type {:02x} at line {}", kind, n + 1)
+        }
+    }
+
+    chunks.sort_by_key(|(addr, _)| *addr);
+
+    let mut runs: Vec<(u64, Vec<u8>)> = Vec::new();
+    for (addr, chunk) in chunks {
+        match runs.last_mut() {
+            Some((start, data)) if addr <= *start + data.len() as u64 + MAX_FILL => {
+                // records may also overlap, later ones win
+                let offset = (addr - *start) as usize;
+                let end = offset + chunk.len();
+                if data.len() < end {
+                    data.resize(end, 0xff);
+                }
+                data[offset..end].copy_from_slice(&chunk);
+            },
+            _ => runs.push((addr, chunk))
+        }
+    }
+
+    Ok(runs)
+}
+
+/// Read object and archive members of a tar file into memory
+///
+/// The tar file may be gzip compressed. Other members are skipped. Long names
+/// come from pax `path` records or GNU `L` entries, and are kept as bytes.
+pub fn read_tar(path: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<u8>)>> {
+    use std::io::Read;
+
+    const BLOCK: usize = 512;
+
+    /// Extended header records this reader understands
+    #[derive(Default)]
+    struct Pax {
+        path: Option<Vec<u8>>,
+        size: Option<u64>
     }
+
+    fn field(header: &[u8]) -> &[u8] {
+        let end = header.iter().position(|&b| b == 0).unwrap_or(header.len());
+        &header[..end]
+    }
+
+    fn octal(header: &[u8]) -> anyhow::Result<u64> {
+        // GNU base-256 encoding for large sizes
+        if header.first().map(|&b| b & 0x80 != 0).unwrap_or(false) {
+            return Ok(header[1..].iter().fold(0, |n, &b| (n << 8) | u64::from(b)));
+        }
+
+        let s = field(header).trim();
+        if s.is_empty() {
+            return Ok(0);
+        }
+        let s = std::str::from_utf8(s)?;
+        Ok(u64::from_str_radix(s, 8)?)
+    }
+
+    /// Parse `<len> <key>=<value>\n` records, `len` counts the whole record
+    fn pax(mut content: &[u8], pax: &mut Pax) -> anyhow::Result<()> {
+        while !content.is_empty() && content[0] != 0 {
+            let len = content.find_byte(b' ').context("bad pax record")?;
+            let len: usize = std::str::from_utf8(&content[..len])?.parse()?;
+            let record = content.get(..len)
+                .and_then(|record| record.strip_suffix(b"\n"))
+                .context("truncated pax record")?;
+            content = &content[len..];
+
+            let (_, record) = record.split_once_str(" ").context("bad pax record")?;
+            let (key, value) = record.split_once_str("=").context("bad pax record")?;
+            match key {
+                b"path" => pax.path = Some(value.to_vec()),
+                b"size" => pax.size = Some(std::str::from_utf8(value)?.parse()?),
+                _ => ()
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut data = read_file(path)?;
+
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut buf = Vec::new();
+        flate2::read::MultiGzDecoder::new(data.as_slice()).read_to_end(&mut buf)
+            .with_context(|| format!("failed to decompress: {}", path.display()))?;
+        data = buf;
+    }
+
+    let mut members = Vec::new();
+    let mut long_name = None;
+    let mut global = Pax::default();
+    let mut local = Pax::default();
+    let mut buf = data.as_slice();
+
+    while buf.len() >= BLOCK {
+        let (header, rest) = buf.split_at(BLOCK);
+
+        // end of archive
+        if header.iter().all(|&b| b == 0) {
+            break
+        }
+
+        let kind = header[156];
+        let size = match local.size.or(global.size) {
+            Some(size) if !matches!(kind, b'x' | b'g' | b'L' | b'K') => size,
+            _ => octal(&header[124..136])?
+        };
+        let size: usize = size.try_into()?;
+        let padded = size.checked_add(BLOCK - 1)
+            .map(|n| n / BLOCK * BLOCK)
+            .filter(|&n| n <= rest.len())
+            .with_context(|| format!("truncated tar file: {}", path.display()))?;
+        let content = &rest[..size];
+        buf = &rest[padded..];
+
+        match kind {
+            // pax extended header for the next member, or for all following members
+            b'x' => pax(content, &mut local)
+                .with_context(|| format!("bad pax header in {}", path.display()))?,
+            b'g' => pax(content, &mut global)
+                .with_context(|| format!("bad pax header in {}", path.display()))?,
+            // GNU long name for the next member
+            b'L' => long_name = Some(field(content).to_vec()),
+            // GNU long link name, links aren't extracted
+            b'K' => (),
+            kind => {
+                let local = std::mem::take(&mut local);
+                let name = match local.path.or_else(|| long_name.take()).or_else(|| global.path.clone()) {
+                    Some(name) => name,
+                    None if &header[257..262] == b"ustar" && header[345] != 0 => {
+                        let mut name = field(&header[345..500]).to_vec();
+                        name.push(b'/');
+                        name.extend_from_slice(field(&header[..100]));
+                        name
+                    },
+                    None => field(&header[..100]).to_vec()
+                };
+
+                if matches!(kind, b'0' | 0) {
+                    let name = path_from_bytes(name);
+
+                    if is_archive_path(&name) || is_object_path(&name) {
+                        if content.is_empty() {
+                            anyhow::bail!("empty input file: {}({})", path.display(), name.display());
+                        }
+                        members.push((name, content.to_vec()));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(members)
+}
+
+/// Path from raw bytes, lossy where paths aren't bytes
+fn path_from_bytes(name: Vec<u8>) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+
+        PathBuf::from(std::ffi::OsString::from_vec(name))
+    }
+
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(&name).into_owned())
+    }
+}
+
+/// Expand `@file` arguments like a linker does
+///
+/// Paths are separated by whitespace, and may be wrapped in single or double
+/// quotes. Response files can reference other response files.
+fn expand_response_file(path: &Path, objects: &mut Vec<PathBuf>, depth: usize)
+    -> anyhow::Result<()>
+{
+    const MAX_DEPTH: usize = 16;
+
+    let file = match path.to_str().and_then(|path| path.strip_prefix('@')) {
+        Some(file) => Path::new(file),
+        None => {
+            objects.push(path.to_owned());
+            return Ok(());
+        }
+    };
+
+    if depth >= MAX_DEPTH {
+        anyhow::bail!("response file nested too deeply: {}", file.display());
+    }
+
+    let content = fs::read_to_string(file)
+        .with_context(|| format!("failed to read response file: {}", file.display()))?;
+
+    let mut chars = content.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue
+        }
+
+        let mut arg = String::new();
+        while let Some(&c) = chars.peek() {
+            match c {
+                '"' | '\'' => {
+                    chars.next();
+                    let mut closed = false;
+                    for c2 in chars.by_ref() {
+                        if c2 == c {
+                            closed = true;
+                            break
+                        }
+                        arg.push(c2);
+                    }
+                    if !closed {
+                        anyhow::bail!("unterminated quote in response file: {}", file.display());
+                    }
+                },
+                c if c.is_whitespace() => break,
+                c => {
+                    chars.next();
+                    arg.push(c);
+                }
+            }
+        }
+
+        expand_response_file(Path::new(&arg), objects, depth + 1)?;
+    }
+
+    Ok(())
+}
+
+/// Expand wildcards in each path component, for shells that don't glob
+fn expand_glob(pattern: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    use std::path::Component;
+
+    fn is_pattern(s: &str) -> bool {
+        s.contains(&['*', '?'][..])
+    }
+
+    if !pattern.to_str().map(is_pattern).unwrap_or(false) {
+        return Ok(vec![pattern.to_owned()]);
+    }
+
+    let mut paths = vec![PathBuf::new()];
+
+    for component in pattern.components() {
+        let part = match component {
+            Component::Normal(part) => part.to_str().filter(|part| is_pattern(part)),
+            _ => None
+        };
+
+        let part = match part {
+            Some(part) => part,
+            None => {
+                paths.iter_mut().for_each(|path| path.push(component));
+                continue
+            }
+        };
+
+        let mut next = Vec::new();
+        for path in paths.iter() {
+            let dir = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+
+            for entry in entries {
+                let name = entry?.file_name();
+                let name_bytes = name.as_encoded_bytes();
+
+                // like shells, `*` doesn't match hidden files
+                if name_bytes.starts_with(b".") && !part.starts_with('.') {
+                    continue
+                }
+
+                if wildcard_match(part.as_bytes(), name_bytes) {
+                    next.push(path.join(&name));
+                }
+            }
+        }
+        next.sort();
+        paths = next;
+    }
+
+    paths.retain(|path| path.exists());
+
+    if paths.is_empty() {
+        anyhow::bail!("no files match pattern: {}", pattern.display());
+    }
+
+    Ok(paths)
+}
+
+pub fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the name position it currently covers up to
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            },
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                // let the last `*` take one more byte and retry from there
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                },
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
 }
 
 struct ObjectFile<'buf> {
@@ -66,14 +786,26 @@ struct ObjectFile<'buf> {
     file: File<'buf>
 }
 
+impl<'buf> ObjectFile<'buf> {
+    fn symbol(&self, pos: SymbolPosition) -> anyhow::Result<object::read::Symbol<'buf, '_>> {
+        if pos.dynamic {
+            let table = self.file.dynamic_symbol_table()
+                .context("not found dynamic symbol table")?;
+            Ok(table.symbol_by_index(pos.sym_idx)?)
+        } else {
+            Ok(self.file.symbol_by_index(pos.sym_idx)?)
+        }
+    }
+}
+
 enum Archive<'buf> {
     Ar(&'buf [u8], ArchiveFile<'buf>),
-    Object(ObjectFile<'buf>)
+    Object(Box<ObjectFile<'buf>>)
 }
 
 enum ArchiveIter<'buf> {
     Ar(&'buf [u8], object::read::archive::ArchiveMemberIterator<'buf>),
-    Object(Option<ObjectFile<'buf>>)
+    Object(Option<Box<ObjectFile<'buf>>>)
 }
 
 impl<'buf> IntoIterator for Archive<'buf> {
@@ -102,19 +834,33 @@ impl<'buf> Iterator for ArchiveIter<'buf> {
         }
 
         match self {
-            ArchiveIter::Ar(buf, iter) => {
+            ArchiveIter::Ar(buf, iter) => loop {
                 let member = try_!(iter.next()?);
                 let name = Cow::Borrowed(member.name());
                 let data = try_!(member.data(*buf));
+
+                // COFF import library members are import descriptors, not objects
+                if let Ok(object::FileKind::CoffImport) = object::FileKind::parse(data) {
+                    continue
+                }
+
                 let file =  try_!(File::parse(data));
-                Some(Ok(ObjectFile { name, file }))
+                break Some(Ok(ObjectFile { name, file }))
             },
-            ArchiveIter::Object(obj) => obj.take().map(Ok)
+            ArchiveIter::Object(obj) => obj.take().map(|obj| Ok(*obj))
         }
     }
 }
 
-fn explorer(list: &[ObjectFile<'_>]) -> anyhow::Result<()> {
+fn explorer(
+    list: Vec<ObjectFile<'_>>,
+    index_cache: Option<&IndexCache>,
+    options: &Options,
+    mmap: bool,
+    mut timings: Timings
+)
+    -> anyhow::Result<()>
+{
     use rustyline::error::ReadlineError;
 
     let config = rustyline::config::Builder::new()
@@ -124,13 +870,51 @@ fn explorer(list: &[ObjectFile<'_>]) -> anyhow::Result<()> {
         .build();
     let mut rl = rustyline::DefaultEditor::with_config(config)?;
 
-    let mut explorer = Explorer::build(list)?;
+    let arena = Arena::default();
+    let mut explorer = Explorer::build(list, &arena, index_cache, options.arch, options.allow_mixed)?;
+    explorer.demangle = !options.no_demangle;
+    explorer.swift = options.swift;
+    explorer.color = match options.color {
+        ColorChoice::Auto => {
+            use std::io::IsTerminal;
+            std::io::stdout().is_terminal()
+        },
+        ColorChoice::Always => true,
+        ColorChoice::Never => false
+    };
+    explorer.mmap = mmap;
+    explorer.addr_width = options.addr_width.map_or(16, |width| width.0);
+    timings.phase("symbol map");
+
+    if !options.quiet {
+        let mut counts = vec![(0, 0); explorer.list.len()];
+
+        for &pos in explorer.symbol_map.values().flatten() {
+            let sym = explorer.list[pos.obj_idx].symbol(pos)?;
+
+            if sym.is_definition() {
+                match sym.kind() {
+                    object::SymbolKind::Text => counts[pos.obj_idx].0 += 1,
+                    object::SymbolKind::Data => counts[pos.obj_idx].1 += 1,
+                    _ => ()
+                }
+            }
+        }
+
+        for (obj, (text, data)) in explorer.list.iter().zip(counts) {
+            println!("{}: {} text, {} data", obj.name.as_bstr(), text, data);
+        }
+    }
     let mut cache = Cache::default();
 
     loop {
         match rl.readline("explorer > ") {
-            Ok(line) => if let Err(err) = exec(&mut explorer, &mut cache, &line) {
-                eprintln!("failed: {:?}", err);
+            Ok(line) => match run(&mut explorer, &mut cache, &line) {
+                Ok(ControlFlow::Continue(())) => (),
+                Ok(ControlFlow::Break(())) => break,
+                // the candidates are already listed
+                Err(err) if err.is::<AmbiguousSymbol>() => (),
+                Err(err) => eprintln!("failed: {:?}", err)
             },
             Err(ReadlineError::WindowResized) => (),
             Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => break,
@@ -141,481 +925,2597 @@ fn explorer(list: &[ObjectFile<'_>]) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn exec<'buf>(explorer: &mut Explorer<'_, 'buf>, cache: &mut Cache<'buf>, line: &str)
-    -> anyhow::Result<()>
+/// Run a command line, a trailing `| [-i] <substr>` keeps only output lines containing `substr`
+fn run<'buf>(explorer: &mut Explorer<'buf>, cache: &mut Cache<'buf>, line: &str)
+    -> anyhow::Result<ControlFlow<()>>
 {
-    let mut iter = line.trim().split_whitespace();
+    // not locked, `search` prints from several threads
+    let mut stdout = std::io::stdout();
+
+    let mut commands = split_command(line)?;
+    let filter = match commands.len() {
+        1 => None,
+        2 => commands.pop(),
+        _ => anyhow::bail!("only one `|` filter is supported")
+    };
+    let args = commands[0].iter().map(String::as_str).collect::<Vec<_>>();
 
-    match iter.next() {
-        Some("obj") => match iter.next() {
-            Some("none") => explorer.current_obj_idx = None,
-            Some(name) => {
-                let obj_idx = explorer.list.iter()
-                    .enumerate()
-                    .find(|(_, obj)| obj.name == name.as_bytes())
-                    .map(|(idx, _)| idx)
-                    .context("not found object")?;
-                explorer.current_obj_idx = Some(obj_idx);
-            }
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return exec(&mut stdout, explorer, cache, &args)
+    };
+
+    let (ignore_case, pattern) = match filter.as_slice() {
+        [flag, pattern] if flag == "-i" => (true, pattern.to_lowercase()),
+        [pattern] => (false, pattern.clone()),
+        _ => anyhow::bail!("usage: <command> | [-i] <substr>")
+    };
+
+    let mut buf = Vec::new();
+    let result = exec(&mut buf, explorer, cache, &args);
+
+    for output_line in buf.lines_with_terminator() {
+        let is_match = if ignore_case {
+            output_line.to_lowercase().contains_str(&pattern)
+        } else {
+            output_line.contains_str(&pattern)
+        };
+
+        if is_match {
+            stdout.write_all(output_line)?;
+        }
+    }
+
+    result
+}
+
+/// Run explorer command lines over object files and collect the output
+#[cfg(test)]
+pub fn run_script(paths: &[&Path], lines: &[&str]) -> anyhow::Result<String> {
+    let bufs = paths.iter()
+        .map(|path| load_file(path, false))
+        .collect::<Result<Vec<_>, _>>()?;
+    let list = paths.iter()
+        .zip(bufs.iter())
+        .map(|(path, buf)| open_object(path, buf, false))
+        .flat_result()
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    let arena = Arena::default();
+    let mut explorer = Explorer::build(list, &arena, None, None, false)?;
+    let mut cache = Cache::default();
+    let mut output = Vec::new();
+
+    for line in lines {
+        let args = split_command(line)?.remove(0);
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        if exec(&mut output, &mut explorer, &mut cache, &args)?.is_break() {
+            break
+        }
+    }
+
+    Ok(String::from_utf8(output)?)
+}
+
+/// Split a command line into arguments like a shell does
+///
+/// Arguments are separated by whitespace, and may be wrapped in single or double
+/// quotes to keep spaces. An unquoted `|` starts a new command.
+fn split_command(line: &str) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut commands = vec![Vec::new()];
+    let mut arg: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                let mut closed = false;
+                for c2 in chars.by_ref() {
+                    if c2 == c {
+                        closed = true;
+                        break
+                    }
+                    arg.push(c2);
+                }
+                if !closed {
+                    anyhow::bail!("unterminated quote");
+                }
+            },
+            '|' => {
+                commands.last_mut().unwrap().extend(arg.take());
+                commands.push(Vec::new());
+            },
+            c if c.is_whitespace() => commands.last_mut().unwrap().extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c)
+        }
+    }
+    commands.last_mut().unwrap().extend(arg.take());
+
+    Ok(commands)
+}
+
+fn exec<'buf>(
+    stdout: &mut (dyn Write + Send),
+    explorer: &mut Explorer<'buf>,
+    cache: &mut Cache<'buf>,
+    args: &[&str]
+)
+    -> anyhow::Result<ControlFlow<()>>
+{
+    let mut iter = args.iter().copied();
+
+    match iter.next() {
+        Some("obj") => match iter.next() {
+            Some("none") => explorer.current_obj_idx = None,
+            Some(name) => {
+                let obj_idx = explorer.select_obj(name, iter.next())?;
+                explorer.current_obj_idx = Some(obj_idx);
+            }
             None => match explorer.current_obj_idx {
-                Some(obj_idx) => println!("{}", explorer.list[obj_idx].name.as_bstr()),
-                None => println!("none")
+                Some(obj_idx) => writeln!(stdout, "{}", explorer.list[obj_idx].name.as_bstr())?,
+                None => writeln!(stdout, "none")?
             }
         },
-        Some("section") => for obj in explorer.list {
-            println!("{:?}: {:#?}",
-                obj.name.as_bstr(),
-                obj.file.sections()
-                    .map(|section| section.name().unwrap_or("<error>").to_string())
-                    .filter(|name| !name.is_empty())
-                    .collect::<Vec<_>>()
-            );
+        Some("ls") | Some("objs") => for (idx, obj) in explorer.list.iter().enumerate() {
+            // shared objects may only keep dynamic symbols
+            let count = match obj.file.symbols().count() {
+                0 => obj.file.dynamic_symbols().count(),
+                count => count
+            };
+            let current = if explorer.current_obj_idx == Some(idx) { "*" } else { " " };
+
+            writeln!(stdout, "{} [{}]\t{}\t{}", current, idx, count, obj.name.as_bstr())?;
+        },
+        Some("alias") => match (iter.next(), iter.next()) {
+            (None, _) => for (alias, name) in explorer.aliases.iter() {
+                writeln!(stdout, "{}\t{}", alias, name.as_bstr())?;
+            },
+            (Some(alias), Some(name)) => {
+                let obj_idx = explorer.find_obj(name)?;
+                let name = explorer.list[obj_idx].name.to_vec();
+                explorer.aliases.insert(alias.to_string(), name);
+            },
+            (Some(_), None) => anyhow::bail!("need object name")
+        },
+        Some("load") => {
+            let path = Path::new(iter.next().context("need object path")?);
+
+            let count = explorer.load(cache, path)?;
+
+            writeln!(stdout, "loaded {} objects", count)?;
+        },
+        Some("unload") => {
+            let name = iter.next().context("need object name")?;
+
+            match explorer.unload(cache, name)? {
+                0 => anyhow::bail!("not found object"),
+                count => writeln!(stdout, "unloaded {} objects", count)?
+            }
+        },
+        Some("section") => match iter.next() {
+            Some("--sizes") => {
+                // decompress to measure instead of trusting the compression header
+                let decompress = match iter.next() {
+                    Some("--uncompressed-sizes") => true,
+                    None => false,
+                    Some(_) => anyhow::bail!("unknown section option")
+                };
+
+                for (obj_idx, obj) in explorer.list.iter().enumerate() {
+                    writeln!(stdout, "{:?}:", obj.name.as_bstr())?;
+                    writeln!(stdout, "  ADDRESS          SIZE             FILE SIZE        UNCOMPRESSED     KIND                 NAME")?;
+
+                    for section in obj.file.sections() {
+                        let name = section.name().unwrap_or("<error>");
+
+                        if name.is_empty() {
+                            continue
+                        }
+
+                        // SIZE is the section header size, which is the compressed size
+                        // for compressed sections, FILE SIZE leaves out the compression
+                        // header and is 0 for bss-like sections
+                        let range = section.compressed_file_range()?;
+                        let uncompressed_size = if range.format == object::CompressionFormat::None {
+                            section.size()
+                        } else if decompress {
+                            cache.uncompressed_size(explorer, obj_idx, section.index())?
+                        } else {
+                            range.uncompressed_size
+                        };
+
+                        write!(stdout, "  {:016x} {:<16} {:<16} {:<16} ",
+                            section.address(),
+                            section.size(),
+                            range.compressed_size,
+                            uncompressed_size
+                        )?;
+
+                        writeln!(stdout, "{:<20} {}", format!("{:?}", section.kind()), name)?;
+                    }
+                }
+            },
+            None => for obj in explorer.list.iter() {
+                writeln!(stdout, "{:?}: {:#?}",
+                    obj.name.as_bstr(),
+                    obj.file.sections()
+                        .map(|section| section.name().unwrap_or("<error>").to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect::<Vec<_>>()
+                )?;
+            },
+            Some(_) => anyhow::bail!("unknown section option")
         },
         Some("search") => {
-            let keywords = iter.collect::<Vec<_>>();
+            let mut sort = None;
+            let mut exact = false;
+            let mut hash = false;
+            let mut versions = false;
+            let mut with_section = false;
+            let mut max = u64::MAX;
+            let mut keywords = Vec::new();
+
+            while let Some(arg) = iter.next() {
+                match arg {
+                    "--exact" => exact = true,
+                    "--hash" => hash = true,
+                    "--versions" => versions = true,
+                    "--section" => with_section = true,
+                    "--max" => {
+                        let n = iter.next().context("need max count")?;
+                        max = n.parse().context("bad max count")?;
+                    },
+                    "--sort" => match iter.next() {
+                        Some("size") => sort = Some(SortBy::Size),
+                        Some("name") => sort = Some(SortBy::Name),
+                        _ => anyhow::bail!("need sort key: size or name")
+                    },
+                    keyword => keywords.push(keyword)
+                }
+            }
+
             if keywords.is_empty() {
                 anyhow::bail!("need keyword");
             }
             let ac = AhoCorasick::new(&keywords)?;
 
-            explorer.symbol_map.iter().fast_for_each(|(mangled_name, syms)| -> anyhow::Result<()> {
-                use smallvec::SmallVec;
-
-                let mut namebuf = SmallVec::<[u8; 1024 * 4]>::new();
-                write!(&mut namebuf, "{}", demangle(mangled_name))?;
-                let name = namebuf.as_slice();
-
-                if ac.is_match(&name) || keywords.iter().any(|w| mangled_name.ends_with(w)) {
-                    for &pos in syms {
-                        let obj = &explorer.list[pos.obj_idx];
-                        let sym = obj.file.symbol_by_index(pos.sym_idx)?;
-                        let kind = explorer.symbol_kind(pos);
-
-                        println!("{:016x} {} {} @ {:?}",
-                            sym.address(),
-                            kind,
-                            mangled_name,
-                            obj.name.as_bstr(),
-                        );
+            cache.init_symmap(explorer)?;
+            if explorer.demangle {
+                cache.init_demangled(explorer);
+            }
+
+            let explorer = &*explorer;
+            let is_match = |cache: &Cache<'buf>, idx: usize, mangled_name: &str| {
+                let name = if explorer.demangle {
+                    cache.demangled_names[idx].as_bytes()
+                } else {
+                    mangled_name.as_bytes()
+                };
+
+                if exact {
+                    let short_name = strip_legacy_hash(name);
+
+                    keywords.iter().any(|w| name == w.as_bytes()
+                        || short_name == w.as_bytes()
+                        || mangled_name == *w)
+                } else {
+                    ac.is_match(name) || keywords.iter().any(|w| mangled_name.ends_with(w))
+                }
+            };
+            let symbol_size = |cache: &Cache<'buf>, pos| explorer.index_cached(cache, pos)
+                .map(|sym| sym.size)
+                .unwrap_or(0);
+            // `object::File` is only read here, so lookups are fine across threads
+            let section_name = |pos: SymbolPosition| if with_section {
+                let obj = &explorer.list[pos.obj_idx];
+                obj.symbol(pos)
+                    .ok()
+                    .and_then(|sym| sym.section_index())
+                    .and_then(|idx| obj.file.section_by_index(idx).ok())
+                    .and_then(|section| section.name().ok().map(str::to_owned))
+            } else {
+                None
+            };
+            let print_match = |stdout: &mut dyn Write, cache: &mut Cache<'buf>, mangled_name: &str, pos|
+                -> anyhow::Result<()>
+            {
+                let hash = if hash { Some(HashColumn(content_hash(explorer, cache, pos)?)) } else { None };
+                let version = if versions { cache.version(explorer, pos)? } else { None };
+                let name = match version {
+                    Some(version) => Cow::Owned(format!("{}@{}", mangled_name, version.as_bstr())),
+                    None => Cow::Borrowed(mangled_name)
+                };
+                print_search_match(stdout, explorer, &name, pos, section_name(pos).as_deref(), hash)
+            };
+
+            let mut count = 0;
+            let mut total_size = 0;
+
+            match sort {
+                Some(sort) => {
+                    let output = Mutex::new(Vec::new());
+
+                    explorer.symbol_map.iter().enumerate().fast_for_each(|(idx, (mangled_name, syms))| -> anyhow::Result<()> {
+                        if is_match(cache, idx, mangled_name) {
+                            let mut output = output.lock().unwrap();
+
+                            for &pos in syms {
+                                output.push((*mangled_name, pos, symbol_size(cache, pos)));
+                            }
+                        }
+
+                        Ok(())
+                    })?;
+
+                    let mut output = output.into_inner().unwrap();
+
+                    match sort {
+                        SortBy::Size => output.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0))),
+                        SortBy::Name => output.sort_by(|a, b| a.0.cmp(b.0))
+                    }
+
+                    count = output.len() as u64;
+                    total_size = output.iter().map(|(_, _, size)| size).sum();
+
+                    for &(mangled_name, pos, _) in output.iter().take(max.try_into().unwrap_or(usize::MAX)) {
+                        print_match(stdout, cache, mangled_name, pos)?;
+                    }
+                },
+                // in symbol map order, so `--max` keeps the same matches every time,
+                // the scan still runs to the end to count what was left out
+                None if hash || versions || max != u64::MAX => {
+                    for (idx, (mangled_name, syms)) in explorer.symbol_map.iter().enumerate() {
+                        if !is_match(cache, idx, mangled_name) {
+                            continue
+                        }
+
+                        for &pos in syms {
+                            count += 1;
+                            total_size += symbol_size(cache, pos);
+
+                            if count <= max {
+                                print_match(stdout, cache, mangled_name, pos)?;
+                            }
+                        }
                     }
+                },
+                // stream matches as they are found
+                None => {
+                    let stdout = Mutex::new(&mut *stdout);
+                    let count_atomic = AtomicU64::new(0);
+                    let size_atomic = AtomicU64::new(0);
+
+                    explorer.symbol_map.iter().enumerate().fast_for_each(|(idx, (mangled_name, syms))| -> anyhow::Result<()> {
+                        if is_match(cache, idx, mangled_name) {
+                            for &pos in syms {
+                                count_atomic.fetch_add(1, Ordering::Relaxed);
+                                size_atomic.fetch_add(symbol_size(cache, pos), Ordering::Relaxed);
+
+                                let section = section_name(pos);
+                                let mut stdout = stdout.lock().unwrap();
+                                print_search_match(&mut **stdout, explorer, mangled_name, pos, section.as_deref(), None)?;
+                            }
+                        }
+
+                        Ok(())
+                    })?;
+
+                    count = count_atomic.into_inner();
+                    total_size = size_atomic.into_inner();
                 }
+            }
+
+            if count > max {
+                writeln!(stdout, "... (truncated, {} more)", count - max)?;
+            }
 
-                Ok(())
-            })?;
+            writeln!(stdout, "matched: {} symbols, total size: {}", count, total_size)?;
         },
-        Some("dump") => {
-            let name = iter.next().context("need symbol name")?;
-            let syms = explorer.get(name)?;
+        Some("tree") => {
+            let keywords = iter.collect::<Vec<_>>();
 
-            let pos = match select(explorer, syms, iter.next())? {
-                Some(pos) => pos,
-                None => {
-                    print_syms_list(explorer, &syms)?;
-                    anyhow::bail!("duplicate symbol");
+            if keywords.is_empty() {
+                anyhow::bail!("need keyword");
+            }
+            let ac = AhoCorasick::new(&keywords)?;
+
+            cache.init_symmap(explorer)?;
+            if explorer.demangle {
+                cache.init_demangled(explorer);
+            }
+
+            let mut tree = PathTree::default();
+
+            for (idx, (mangled_name, syms)) in explorer.symbol_map.iter().enumerate() {
+                let name: &str = if explorer.demangle {
+                    &cache.demangled_names[idx]
+                } else {
+                    mangled_name
+                };
+
+                if !ac.is_match(name) && !keywords.iter().any(|w| mangled_name.ends_with(w)) {
+                    continue
+                }
+
+                let name = strip_legacy_hash(name.as_bytes()).to_str()?;
+                let path = split_path(name);
+
+                for &pos in syms {
+                    let size = explorer.index_cached(cache, pos)
+                        .map(|sym| sym.size)
+                        .unwrap_or(0);
+                    tree.insert(&path, size);
+                }
+            }
+
+            tree.print(stdout, "", 0)?;
+            writeln!(stdout, "matched: {} symbols, total size: {}", tree.count, tree.size)?;
+        },
+        Some("export") => {
+            let usage = "usage: export <pattern> <dir>";
+            let pattern = iter.next().context(usage)?;
+            let dir = Path::new(iter.next().context(usage)?);
+
+            cache.init_symmap(explorer)?;
+            if explorer.demangle {
+                cache.init_demangled(explorer);
+            }
+
+            let mut matches = Vec::new();
+
+            for (idx, (mangled_name, syms)) in explorer.symbol_map.iter().enumerate() {
+                let name: &str = if explorer.demangle {
+                    &cache.demangled_names[idx]
+                } else {
+                    mangled_name
+                };
+
+                if name.contains(pattern) || mangled_name.ends_with(pattern) {
+                    matches.extend(syms.iter().map(|&pos| (name.to_owned(), pos)));
+                }
+            }
+
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create dir: {}", dir.display()))?;
+
+            let mut used = HashSet::new();
+            let mut written = 0;
+            let mut skipped = 0;
+
+            for (name, pos) in matches {
+                // undefined, absolute and bss symbols have no bytes
+                let sym = match explorer.index(cache, pos) {
+                    Ok(sym) if sym.size != 0 => sym,
+                    _ => {
+                        skipped += 1;
+                        continue
+                    }
+                };
+                let section = explorer.list[pos.obj_idx].file.section_by_index(sym.section_idx)?;
+                if matches!(section.kind(), object::SectionKind::UninitializedData | object::SectionKind::UninitializedTls) {
+                    skipped += 1;
+                    continue
                 }
+
+                let data = explorer.dump(cache, &sym)?;
+
+                // same-named symbols from other objects get a numbered suffix
+                let base = sanitize_file_name(&name);
+                let mut file_name = format!("{}.bin", base);
+                let mut n = 1;
+                while !used.insert(file_name.clone()) {
+                    file_name = format!("{}-{}.bin", base, n);
+                    n += 1;
+                }
+
+                let path = dir.join(&file_name);
+                fs::write(&path, data.as_ref())
+                    .with_context(|| format!("failed to write: {}", path.display()))?;
+                written += 1;
+            }
+
+            writeln!(stdout, "wrote {} files to {}, skipped {} without bytes", written, dir.display(), skipped)?;
+        },
+        Some("symbols") => {
+            let mut sort = None;
+            let mut kinds = None;
+            let mut hash = false;
+            let mut versions = false;
+            let mut name = None;
+
+            while let Some(arg) = iter.next() {
+                match arg {
+                    "--sort" => match iter.next() {
+                        Some("size") => sort = Some(SortBy::Size),
+                        Some("name") => sort = Some(SortBy::Name),
+                        _ => anyhow::bail!("need sort key: size or name")
+                    },
+                    "--kind" => kinds = Some(iter.next().context("need symbol kinds, e.g. tT")?),
+                    "--hash" => hash = true,
+                    "--versions" => versions = true,
+                    arg => name = Some(arg)
+                }
+            }
+
+            let obj_idx = match name {
+                Some(name) => explorer.find_obj(name)?,
+                None => explorer.current_obj_idx.context("need object name or `obj` selected")?
             };
+            let obj = &explorer.list[obj_idx];
+
+            // shared objects may only keep dynamic symbols
+            let dynamic = obj.file.symbols().next().is_none();
+            let syms = if dynamic { obj.file.dynamic_symbols() } else { obj.file.symbols() };
+
+            cache.init_symmap(explorer)?;
+
+            let mut output = Vec::new();
+            for sym in syms {
+                if matches!(sym.kind(), object::SymbolKind::Section | object::SymbolKind::File) {
+                    continue
+                }
+
+                let name = sym.name()?;
+                if name.is_empty() {
+                    continue
+                }
+
+                let pos = SymbolPosition { obj_idx, sym_idx: sym.index(), dynamic };
+                let kind = explorer.symbol_kind(pos);
+
+                if let Some(kinds) = kinds {
+                    if !kinds.contains(kind) {
+                        continue
+                    }
+                }
+
+                let address = if sym.is_undefined() { None } else { Some(sym.address()) };
+                let size = explorer.index_cached(cache, pos)
+                    .map(|sym| sym.size)
+                    .unwrap_or(0);
+
+                output.push((address, kind, size, name, pos));
+            }
+
+            match sort {
+                Some(SortBy::Size) => output.sort_by_key(|(_, _, size, _, _)| std::cmp::Reverse(*size)),
+                Some(SortBy::Name) => output.sort_by(|a, b| a.3.cmp(b.3)),
+                None => ()
+            }
+
+            for &(address, kind, size, name, pos) in output.iter() {
+                // look up everything first, so a failure doesn't leave half a line
+                let version = if versions { cache.version(explorer, pos)? } else { None };
+                let hash = if hash { Some(HashColumn(content_hash(explorer, cache, pos)?)) } else { None };
+
+                match address {
+                    Some(address) => write!(stdout, "{:016x}", address)?,
+                    None => write!(stdout, "{:16}", "")?
+                }
+                write!(stdout, " {} {:<8} {}", kind, size, explorer.display_name(name))?;
+
+                if let Some(version) = version {
+                    write!(stdout, "@{}", version.as_bstr())?;
+                }
+
+                match hash {
+                    Some(hash) => writeln!(stdout, "\t{}", hash)?,
+                    None => writeln!(stdout)?
+                }
+            }
+
+            writeln!(stdout, "symbols: {}", output.len())?;
+        },
+        Some("hash") => {
+            let pos = select_symbol(explorer, &mut iter)?;
+            let hash = content_hash(explorer, cache, pos)?.context("symbol has no bytes")?;
+            let obj = &explorer.list[pos.obj_idx];
+            let name = obj.symbol(pos)?.name()?;
+
+            writeln!(stdout, "{}\t{} @ {:?}", HexStr(&hash), explorer.display_name(name), obj.name.as_bstr())?;
+        },
+        Some("dump") => {
+            let mut json = false;
+            let mut bytes = false;
+            let mut detail = false;
+            let mut format = None;
+            let mut base = 0;
+            let mut args = Vec::new();
+
+            while let Some(arg) = iter.next() {
+                match arg {
+                    "--json" => json = true,
+                    "--bytes" => bytes = true,
+                    "--base" => {
+                        let hex = iter.next().context("need base address")?;
+                        base = u64::from_str_radix(hex.trim_start_matches("0x"), 16)
+                            .context("bad base address")?;
+                    },
+                    "--detail" => detail = true,
+                    "--format" => format = match iter.next() {
+                        Some("c") => Some(DumpFormat::C),
+                        Some("raw") => {
+                            let path = iter.next().context("raw format needs an output path")?;
+                            Some(DumpFormat::Raw(Path::new(path)))
+                        },
+                        _ => anyhow::bail!("need dump format: c or raw")
+                    },
+                    arg => args.push(arg)
+                }
+            }
 
+            let pos = select_symbol(explorer, &mut args.into_iter())?;
             let sym = explorer.index(cache, pos)?;
+            // annotations are best effort, the bytes and disassembly are still printed
+            let relocs = if matches!(sym.kind, 't' | 'T') {
+                explorer.reloc(cache, &sym).unwrap_or_else(|err| {
+                    eprintln!("WARN: skip relocations: {:?}", err);
+                    Vec::new()
+                })
+            } else {
+                Vec::new()
+            };
             let data = explorer.dump(cache, &sym)?;
             let data = data.as_ref();
             let obj = &explorer.list[sym.pos.obj_idx];
             let section = obj.file.section_by_index(sym.section_idx)?;
-            let address = sym.address - section.address();
+            // relocation offsets are section relative
+            let offset = sym.address - section.address();
+
+            // `--base` shifts every printed address
+            let address = base.checked_add(sym.address).context("base address overflows")?;
+
+            let name = obj.symbol(sym.pos)?.name()?;
+
+            match format {
+                Some(DumpFormat::C) => {
+                    print_c_array(stdout, name, data)?;
+                    return Ok(ControlFlow::Continue(()));
+                },
+                Some(DumpFormat::Raw(path)) => {
+                    fs::write(path, data)
+                        .with_context(|| format!("failed to write: {}", path.display()))?;
+                    writeln!(stdout, "wrote {} bytes to {}", data.len(), path.display())?;
+                    return Ok(ControlFlow::Continue(()));
+                },
+                None => ()
+            }
+
+            if json {
+                return print_dump_json(stdout, explorer, &sym, name, address, data)
+                    .map(|_| ControlFlow::Continue(()));
+            }
 
-            println!("{:016x} {} {} @ {}/{}",
-                sym.address,
+            writeln!(stdout, "{:016x} {} {} {} @ {}/{}",
+                address,
                 sym.kind,
                 sym.size,
+                explorer.display_name(name),
                 obj.name.as_bstr(),
                 section.name()?
-            );
+            )?;
 
-            if matches!(sym.kind, 't' | 'T') {
-                let disasm = (explorer.disasm)()?;
+            if matches!(sym.kind, 't' | 'T') && explorer.has_disasm(sym.pos.obj_idx) {
+                let mut disasm = explorer.disasm(sym.pos.obj_idx)?;
+                if detail {
+                    disasm.set_detail(true)
+                        .map_err(|err| anyhow::format_err!("enable detail failed: {:?}", err))?;
+                }
                 let insns = disasm.disasm_all(data, address)
                     .map_err(|err| anyhow::format_err!("disasm failed: {:?}", err))?;
 
+                if !relocs.is_empty() {
+                    writeln!(stdout, "note: {} relocations in range, operands may be unrelocated", relocs.len())?;
+                }
+
+                let bytes_width = if bytes {
+                    let addr_width = insns.iter()
+                        .map(|ins| format!("{:#x}", ins.address()).len())
+                        .max()
+                        .unwrap_or(0);
+                    let width = insns.iter().map(|ins| ins.bytes().len()).max().unwrap_or(0);
+                    Some((addr_width, width))
+                } else {
+                    None
+                };
+
                 for ins in insns.iter() {
-                    println!("{}", ins);
+                    let start = ins.address() - address + offset;
+                    let end = start + ins.bytes().len() as u64;
+                    let mut targets = Vec::new();
+
+                    for reloc in relocs.iter().filter(|reloc| (start..end).contains(&reloc.offset)) {
+                        let (_, _, name) = reloc.resolve_target(obj)?;
+                        targets.push(name);
+                    }
+
+                    let line = InsnDisplay { ins, color: explorer.color, bytes_width };
+
+                    if targets.is_empty() {
+                        writeln!(stdout, "{}", line)?;
+                    } else {
+                        writeln!(stdout, "{}\t; reloc: {}", line, targets.join(", "))?;
+                    }
+
+                    if detail {
+                        print_insn_detail(stdout, &disasm, ins)?;
+                    }
                 }
             } else {
-                let stdout = std::io::stdout();
-                let mut stdout = stdout.lock();
-
-                print_pretty_bytes(&mut stdout, address, data)?;
+                print_pretty_bytes(stdout, address, data, explorer.addr_width)?;
+            }
+        },
+        Some("disasm") => {
+            let usage = "usage: disasm <objname> <section> <start-hex> <len>";
+            let obj_name = iter.next().context(usage)?;
+            let section_name = iter.next().context(usage)?;
+            let start = iter.next().context(usage)?;
+            let start = u64::from_str_radix(start.trim_start_matches("0x"), 16)
+                .context("bad start address")?;
+            let len = iter.next().context(usage)?;
+            let len = match len.strip_prefix("0x") {
+                Some(len) => u64::from_str_radix(len, 16),
+                None => len.parse()
+            }.context("bad length")?;
+
+            let obj_idx = explorer.find_obj(obj_name)?;
+            let section = explorer.list[obj_idx].file.section_by_name(section_name)
+                .context("not found section")?;
+
+            let data = explorer.section_range(cache, obj_idx, section.index(), start, len)?;
+            let disasm = explorer.disasm(obj_idx)?;
+            let insns = disasm.disasm_all(data.as_ref(), start)
+                .map_err(|err| anyhow::format_err!("disasm failed: {:?}", err))?;
+
+            for ins in insns.iter() {
+                writeln!(stdout, "{}", InsnDisplay { ins, color: explorer.color, bytes_width: None })?;
             }
         },
         Some("reloc") => {
+            let mut kind_filter = None;
+            let mut json = false;
+            let mut all = false;
+            let mut raw = false;
+            let mut hex = false;
+            let mut args = Vec::new();
+
+            while let Some(arg) = iter.next() {
+                match arg {
+                    "--json" => json = true,
+                    "--raw" => raw = true,
+                    "--hex" => hex = true,
+                    "--all" => all = true,
+                    "--type" => {
+                        let kind = iter.next().context("need relocation type")?;
+                        kind_filter = Some(kind.to_ascii_lowercase());
+                    },
+                    arg => args.push(arg)
+                }
+            }
+
+            let filter = |list: &mut Vec<Relocation>| if let Some(kind_filter) = kind_filter.as_ref() {
+                list.retain(|reloc| reloc.kind_name().to_ascii_lowercase().contains(kind_filter.as_str()));
+            };
+
+            if all {
+                if json {
+                    anyhow::bail!("--json is not supported with --all");
+                }
+
+                let obj_idx = match args.first() {
+                    Some(name) => explorer.find_obj(name)?,
+                    None => explorer.current_obj_idx.context("need object name or `obj` selected")?
+                };
+                let obj = &explorer.list[obj_idx];
+                let mut kinds = BTreeMap::new();
+
+                for section in obj.file.sections() {
+                    if section.relocations().next().is_none() {
+                        continue
+                    }
+
+                    let mut list = explorer.section_reloc(cache, obj_idx, section.index())?;
+                    filter(&mut list);
+
+                    if list.is_empty() {
+                        continue
+                    }
+
+                    writeln!(stdout, "{}:", section.name().unwrap_or("<error>"))?;
+                    print_reloc_table(stdout, explorer, obj, &list, raw, hex)?;
+
+                    for reloc in list.iter() {
+                        *kinds.entry(reloc.kind_name()).or_default() += 1;
+                    }
+                }
+
+                print_reloc_kinds(stdout, &kinds)?;
+
+                return Ok(ControlFlow::Continue(()));
+            }
+
+            let pos = select_symbol(explorer, &mut args.into_iter())?;
+            let sym = explorer.index(cache, pos)?;
+            let mut list = explorer.reloc(cache, &sym)?;
+            let obj = &explorer.list[sym.pos.obj_idx];
+
+            filter(&mut list);
+
+            if json {
+                writeln!(stdout, "[")?;
+                for (i, reloc) in list.iter().enumerate() {
+                    let (ty, addr, name) = reloc.resolve_target(obj)?;
+                    let name = if raw { Cow::Borrowed(name.as_str()) } else { explorer.display_name(&name) };
+
+                    writeln!(stdout,
+                        "  {{\"offset\":{},\"addend\":{},\"type\":{},\"kind\":{},\"address\":{},\"name\":{}}}{}",
+                        reloc.offset,
+                        reloc.addend,
+                        JsonStr(ty),
+                        JsonStr(&reloc.kind_name()),
+                        addr,
+                        JsonStr(&name),
+                        if i + 1 < list.len() { "," } else { "" }
+                    )?;
+                }
+                writeln!(stdout, "]")?;
+
+                return Ok(ControlFlow::Continue(()));
+            }
+
+            print_reloc_table(stdout, explorer, obj, &list, raw, hex)?;
+
+            let mut kinds = BTreeMap::new();
+            for reloc in list.iter() {
+                *kinds.entry(reloc.kind_name()).or_default() += 1;
+            }
+            print_reloc_kinds(stdout, &kinds)?;
+        },
+        Some("diffasm") => {
             let name = iter.next().context("need symbol name")?;
             let syms = explorer.get(name)?;
 
-            let pos = match select(explorer, syms, iter.next())? {
-                Some(pos) => pos,
-                None => {
-                    print_syms_list(explorer, &syms)?;
-                    anyhow::bail!("duplicate symbol");
-                }
-            };
+            let defined = syms.iter()
+                .filter_map(|&pos| explorer.index(cache, pos).ok())
+                .filter(|sym| matches!(sym.kind, 't' | 'T'))
+                .collect::<Vec<_>>();
+
+            let (old_sym, new_sym) = match defined.as_slice() {
+                [old_sym, new_sym] => (old_sym, new_sym),
+                _ => {
+                    print_syms_list(explorer, syms)?;
+                    anyhow::bail!("need exactly two definitions, found {}", defined.len());
+                }
+            };
+
+            let old_insns = explorer.disasm_text(cache, old_sym)?;
+            let new_insns = explorer.disasm_text(cache, new_sym)?;
+
+            writeln!(stdout, "--- {:016x} {} @ {}",
+                old_sym.address,
+                old_sym.size,
+                explorer.list[old_sym.pos.obj_idx].name.as_bstr()
+            )?;
+            writeln!(stdout, "+++ {:016x} {} @ {}",
+                new_sym.address,
+                new_sym.size,
+                explorer.list[new_sym.pos.obj_idx].name.as_bstr()
+            )?;
+
+            for op in diff_lines(&old_insns, &new_insns) {
+                match op {
+                    DiffOp::Equal(i) => writeln!(stdout, "  {}", old_insns[i])?,
+                    DiffOp::Delete(i) => writeln!(stdout, "- {}", old_insns[i])?,
+                    DiffOp::Insert(j) => writeln!(stdout, "+ {}", new_insns[j])?
+                }
+            }
+        },
+        Some("whatis") => {
+            const CONTEXT_INSNS: usize = 4;
+            const CONTEXT_BYTES: u64 = 32;
+
+            let addr = iter.next().context("usage: whatis <hex> [objname]")?;
+            let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16)
+                .context("bad address")?;
+            let obj_idx = match iter.next() {
+                Some(name) => explorer.find_obj(name)?,
+                None => explorer.current_obj_idx.context("need object name or `obj` selected")?
+            };
+            let obj = &explorer.list[obj_idx];
+
+            cache.init_symmap(explorer)?;
+
+            // shared objects may only keep dynamic symbols
+            let dynamic = obj.file.symbols().next().is_none();
+            let syms = if dynamic { obj.file.dynamic_symbols() } else { obj.file.symbols() };
+
+            // prefer text, objects have every section at address 0
+            let mut found = None;
+            for sym in syms {
+                if sym.is_undefined() || sym.name().map(str::is_empty).unwrap_or(true)
+                    || matches!(sym.kind(), object::SymbolKind::Section | object::SymbolKind::File)
+                {
+                    continue
+                }
+
+                let pos = SymbolPosition { obj_idx, sym_idx: sym.index(), dynamic };
+                let sym = match explorer.index_cached(cache, pos) {
+                    Ok(sym) => sym,
+                    Err(_) => continue
+                };
+
+                let is_inside = match sym.address.checked_add(sym.size.max(1)) {
+                    Some(end) => (sym.address..end).contains(&addr),
+                    None if addr >= sym.address => anyhow::bail!("address out of range"),
+                    None => false
+                };
+                let is_better = found.as_ref()
+                    .map(|found: &Symbol| !matches!(found.kind, 't' | 'T') && matches!(sym.kind, 't' | 'T'))
+                    .unwrap_or(true);
+
+                if is_inside && is_better {
+                    found = Some(sym);
+                }
+            }
+
+            let (section_idx, start, size, kind) = match found.as_ref() {
+                Some(sym) => {
+                    let name = obj.symbol(sym.pos)?.name()?;
+                    let section = obj.file.section_by_index(sym.section_idx)?;
+
+                    writeln!(stdout, "{:016x} is {}+{:#x} ({} {}) @ {}/{}",
+                        addr,
+                        explorer.display_name(name),
+                        addr - sym.address,
+                        sym.kind,
+                        sym.size,
+                        obj.name.as_bstr(),
+                        section.name()?
+                    )?;
+
+                    (sym.section_idx, sym.address, sym.size, section.kind())
+                },
+                None => {
+                    let mut found = None;
+                    for section in obj.file.sections() {
+                        let is_inside = match section.address().checked_add(section.size()) {
+                            Some(end) => (section.address()..end).contains(&addr),
+                            None if addr >= section.address() => anyhow::bail!("address out of range"),
+                            None => false
+                        };
+                        let is_better = found.as_ref()
+                            .map(|found: &object::Section| found.kind() != object::SectionKind::Text
+                                && section.kind() == object::SectionKind::Text)
+                            .unwrap_or(true);
+
+                        if is_inside && is_better {
+                            found = Some(section);
+                        }
+                    }
+                    let section = found.context("address is not in any section")?;
+
+                    writeln!(stdout, "{:016x} is {}+{:#x} @ {}",
+                        addr,
+                        section.name()?,
+                        addr - section.address(),
+                        obj.name.as_bstr()
+                    )?;
+
+                    (section.index(), section.address(), section.size(), section.kind())
+                }
+            };
+
+            if kind == object::SectionKind::Text {
+                // decode from the symbol start so instructions stay aligned
+                let data = explorer.section_range(cache, obj_idx, section_idx, start, size)?;
+                let disasm = explorer.disasm(obj_idx)?;
+                let insns = disasm.disasm_all(data.as_ref(), start)
+                    .map_err(|err| anyhow::format_err!("disasm failed: {:?}", err))?;
+                let ins_end = |ins: &capstone::Insn| ins.address().saturating_add(ins.bytes().len() as u64);
+                let idx = insns.iter()
+                    .position(|ins| ins_end(ins) > addr)
+                    .unwrap_or(insns.len());
+
+                for ins in insns.iter().skip(idx.saturating_sub(CONTEXT_INSNS)).take(CONTEXT_INSNS * 2 + 1) {
+                    let marker = if ins.address() <= addr && addr < ins_end(ins) {
+                        "> "
+                    } else {
+                        "  "
+                    };
+                    writeln!(stdout, "{}{}", marker, InsnDisplay { ins, color: explorer.color, bytes_width: None })?;
+                }
+            } else if !matches!(kind, object::SectionKind::UninitializedData | object::SectionKind::UninitializedTls) {
+                let section = obj.file.section_by_index(section_idx)?;
+                let section_end = section.address().checked_add(section.size())
+                    .context("address out of range")?;
+                let range_start = (addr.saturating_sub(CONTEXT_BYTES) & !0xf).max(section.address());
+                let range_end = addr.checked_add(CONTEXT_BYTES)
+                    .context("address out of range")?
+                    .min(section_end);
+                let data = explorer.section_range(cache, obj_idx, section_idx, range_start, range_end - range_start)?;
+
+                print_pretty_bytes(stdout, range_start, data.as_ref(), explorer.addr_width)?;
+            }
+        },
+        Some("callgraph") => {
+            let name = iter.next().context("need symbol name")?;
+            let depth = match iter.next() {
+                Some(depth) => depth.parse().context("bad depth")?,
+                None => 3
+            };
+
+            let pos = explorer.get(name)?
+                .iter()
+                .copied()
+                .find(|&pos| matches!(explorer.symbol_kind(pos), 't' | 'T'))
+                .context("not found text symbol")?;
+
+            print_callgraph(stdout, explorer, cache, pos, depth, &mut Vec::new())?;
+        },
+        Some("find-dup") => {
+            let mut count = 0;
+
+            for (mangled_name, syms) in explorer.symbol_map.iter() {
+                let defs = syms.iter()
+                    .copied()
+                    .filter(|&pos| explorer.is_strong_definition(pos))
+                    .collect::<Vec<_>>();
+
+                if defs.len() > 1 {
+                    count += 1;
+
+                    writeln!(stdout, "{}", explorer.display_name(mangled_name))?;
+                    for pos in defs {
+                        writeln!(stdout, "\t{} by {:?}",
+                            explorer.symbol_kind(pos),
+                            explorer.list[pos.obj_idx].name.as_bstr()
+                        )?;
+                    }
+                }
+            }
+
+            writeln!(stdout, "duplicate: {}", count)?;
+        },
+        Some("set") => match (iter.next(), iter.next()) {
+            (Some("demangle"), Some("on")) => explorer.demangle = true,
+            (Some("demangle"), Some("off")) => explorer.demangle = false,
+            (Some("demangle"), None) => writeln!(stdout, "{}", if explorer.demangle { "on" } else { "off" })?,
+            _ => anyhow::bail!("unknown setting")
+        },
+        Some("quit") | Some("exit") | Some("q") => return Ok(ControlFlow::Break(())),
+        Some(cmd) if !cmd.trim().is_empty() => anyhow::bail!("unknown command"),
+        _ => ()
+    }
+
+    Ok(ControlFlow::Continue(()))
+}
+
+struct Explorer<'buf> {
+    list: Vec<ObjectFile<'buf>>,
+    /// buffers of objects loaded at runtime
+    arena: &'buf Arena,
+    /// of the first object, others may differ with `--allow-mixed`
+    arch: object::Architecture,
+    format: object::BinaryFormat,
+    arch_override: Option<DisasmArch>,
+    allow_mixed: bool,
+    symbol_map: IndexMap<&'buf str, Vec<SymbolPosition>>,
+    current_obj_idx: Option<usize>,
+    last_pos: Option<SymbolPosition>,
+    demangle: bool,
+    swift: bool,
+    color: bool,
+    mmap: bool,
+    addr_width: usize,
+    /// short name -> object name
+    aliases: BTreeMap<String, Vec<u8>>
+}
+
+/// Append-only store of file buffers
+///
+/// A buffer is only dropped with the arena, so slices of it can be borrowed
+/// for as long as the arena is.
+#[derive(Default)]
+struct Arena(Mutex<Vec<FileData>>);
+
+impl Arena {
+    fn alloc(&self, data: FileData) -> &[u8] {
+        // SAFETY: the bytes of a `FileData` are mapped or on the heap, so they don't
+        // move with it, and entries are never removed or mutated before the arena drops
+        let buf = unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) };
+        self.0.lock().unwrap_or_else(|err| err.into_inner()).push(data);
+        buf
+    }
+}
+
+#[derive(Default)]
+struct Cache<'buf> {
+    symmap_list: Vec<object::read::SymbolMap<object::read::SymbolMapName<'buf>>>,
+    decompress_sections: HashMap<(usize, object::read::SectionIndex), (u64, Vec<u8>)>,
+    reloc_list: Vec<HashMap<object::read::SectionIndex, Vec<(u64, object::read::Relocation)>>>,
+    /// demangled `symbol_map` keys, in the same order
+    demangled_names: Vec<Box<str>>,
+    /// ELF symbol versions of each object, by static and by dynamic symbol index
+    versions_list: Vec<[HashMap<usize, &'buf [u8]>; 2]>
+}
+
+#[derive(Clone, Copy)]
+enum SortBy {
+    Size,
+    Name
+}
+
+/// Matched symbols grouped by `::` path segments
+#[derive(Default)]
+struct PathTree<'a> {
+    size: u64,
+    count: u64,
+    children: BTreeMap<&'a str, PathTree<'a>>
+}
+
+impl<'a> PathTree<'a> {
+    fn insert(&mut self, path: &[&'a str], size: u64) {
+        self.size += size;
+        self.count += 1;
+
+        if let Some((first, rest)) = path.split_first() {
+            self.children.entry(first).or_default().insert(rest, size);
+        }
+    }
+
+    /// Print children, largest first, merging single-child chains into one line
+    fn print(&self, stdout: &mut dyn Write, prefix: &str, indent: usize) -> anyhow::Result<()> {
+        let mut children = self.children.iter().collect::<Vec<_>>();
+        children.sort_by_key(|(_, node)| std::cmp::Reverse(node.size));
+
+        for (name, mut node) in children {
+            let mut label = format!("{}{}", prefix, name);
+
+            // a chain only merges when no symbol ends in the middle of it
+            while node.children.len() == 1 {
+                let (child_name, child) = node.children.iter().next().unwrap();
+
+                if child.count != node.count {
+                    break
+                }
+
+                label.push_str("::");
+                label.push_str(child_name);
+                node = child;
+            }
+
+            writeln!(stdout, "{:indent$}{}\t{}\t{}", "", label, node.size, node.count, indent = indent)?;
+            node.print(stdout, "", indent + 2)?;
+        }
+
+        Ok(())
+    }
+}
+
+enum DumpFormat<'a> {
+    /// `uint8_t` array literal
+    C,
+    /// bytes written to a file
+    Raw(&'a Path)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct SymbolPosition {
+    obj_idx: usize,
+    sym_idx: object::read::SymbolIndex,
+    /// index into dynamic symbol table
+    dynamic: bool
+}
+
+struct Symbol {
+    pos: SymbolPosition,
+    section_idx: object::read::SectionIndex,
+    address: u64,
+    size: u64,
+    kind: char
+}
+
+#[derive(Debug)]
+struct Relocation {
+    offset: u64,
+    target: RelocationTarget,
+    addend: i64,
+    kind: object::RelocationKind
+}
+
+#[derive(Debug)]
+enum RelocationTarget {
+    Symbol(object::read::SymbolIndex),
+    Section(object::read::SectionIndex)
+}
+
+impl Relocation {
+    fn new(offset: u64, reloc: &object::read::Relocation) -> anyhow::Result<Relocation> {
+        Ok(Relocation {
+            offset,
+            target: match reloc.target() {
+                object::read::RelocationTarget::Symbol(idx) => RelocationTarget::Symbol(idx),
+                object::read::RelocationTarget::Section(idx) => RelocationTarget::Section(idx),
+                _ => anyhow::bail!("not support target: {:?}", reloc)
+            },
+            addend: reloc.addend(),
+            kind: reloc.kind()
+        })
+    }
+
+    fn kind_name(&self) -> String {
+        match self.kind {
+            object::RelocationKind::MachO { value, relative } =>
+                format!("MachO({}{})", value, if relative { ",pc" } else { "" }),
+            kind => format!("{:?}", kind)
+        }
+    }
+
+    /// Returns target type, address and name
+    fn resolve_target(&self, obj: &ObjectFile<'_>) -> anyhow::Result<(&'static str, u64, String)> {
+        Ok(match &self.target {
+            RelocationTarget::Symbol(idx) => {
+                let sym = obj.file.symbol_by_index(*idx)?;
+                let addr = sym.address();
+                let name = match (sym.name()?, sym.section_index()) {
+                    ("", Some(section_idx)) => obj.file.section_by_index(section_idx)?
+                        .name()?
+                        .to_string(),
+                    (name, _) => name.to_string()
+                };
+                ("symbol", addr, name)
+            },
+            RelocationTarget::Section(idx) => {
+                let section = obj.file.section_by_index(*idx)?;
+                let addr = section.address();
+                let name = section.name()?.to_string();
+                ("section", addr, name)
+            }
+        })
+    }
+}
+
+impl<'buf> Explorer<'buf> {
+    fn build(
+        list: Vec<ObjectFile<'buf>>,
+        arena: &'buf Arena,
+        index_cache: Option<&IndexCache>,
+        arch_override: Option<DisasmArch>,
+        allow_mixed: bool
+    )
+        -> anyhow::Result<Explorer<'buf>>
+    {
+        let (arch, format) = {
+            let obj = list.first().context("not found object")?;
+            (obj.file.architecture(), obj.file.format())
+        };
+
+        for obj in list.iter() {
+            check_consistent(obj, arch, format, arch_override.is_none(), allow_mixed)?;
+        }
+
+        // start anyway, `dump` falls back to a hexdump in these objects
+        let mut unsupported = Vec::new();
+        for obj in list.iter() {
+            let arch = obj.file.architecture();
+
+            if disasm_arch(arch_override, arch).is_none() && !unsupported.contains(&arch) {
+                eprintln!("WARN: no disassembler for {:?}, text symbols are shown as bytes", arch);
+                unsupported.push(arch);
+            }
+        }
+
+        let cached_map = match index_cache.map(|cache| cache.load(&list, arch, format)) {
+            Some(Ok(map)) => map,
+            Some(Err(err)) => {
+                eprintln!("WARN: ignore broken index cache: {:?}", err);
+                None
+            },
+            None => None
+        };
+
+        let symbol_map = match cached_map {
+            Some(symbol_map) => symbol_map,
+            None => {
+                let symbol_map = build_symbol_map(&list)?;
+
+                if let Some(cache) = index_cache {
+                    if let Err(err) = cache.save(&list, arch, format, &symbol_map) {
+                        eprintln!("WARN: save index cache failed: {:?}", err);
+                    }
+                }
+
+                symbol_map
+            }
+        };
+
+        Ok(Explorer {
+            list, arena, arch, format, arch_override, allow_mixed,
+            symbol_map,
+            current_obj_idx: None,
+            last_pos: None,
+            demangle: true,
+            swift: false,
+            color: false,
+            mmap: true,
+            addr_width: 16,
+            aliases: BTreeMap::new()
+        })
+    }
+
+    /// Load an object or archive at runtime and rebuild the symbol map
+    ///
+    /// The buffer goes to the arena, so it outlives the objects and anything
+    /// borrowed from them. Returns how many objects were loaded.
+    fn load(&mut self, cache: &mut Cache<'buf>, path: &Path) -> anyhow::Result<usize> {
+        let buf = self.arena.alloc(load_file(path, self.mmap)?);
+        let objs = open_object(path, buf, false)?
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for obj in objs.iter() {
+            check_consistent(obj, self.arch, self.format, self.arch_override.is_none(), self.allow_mixed)?;
+        }
+
+        let count = objs.len();
+        self.list.extend(objs);
+        self.symbol_map = build_symbol_map(&self.list)?;
+        // cached entries are by object index
+        *cache = Cache::default();
+
+        Ok(count)
+    }
+
+    /// Remove objects by name and the aliases to them, returns how many were removed
+    ///
+    /// Their bytes stay in the arena until the explorer exits.
+    fn unload(&mut self, cache: &mut Cache<'buf>, name: &str) -> anyhow::Result<usize> {
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.list)
+            .into_iter()
+            .partition(|obj| obj.name == name.as_bytes());
+        self.list = kept;
+
+        if !removed.is_empty() {
+            // object indices have shifted
+            match build_symbol_map(&self.list) {
+                Ok(symbol_map) => self.symbol_map = symbol_map,
+                Err(err) => {
+                    self.list.extend(removed);
+                    return Err(err);
+                }
+            }
+            self.aliases.retain(|_, target| target.as_slice() != name.as_bytes());
+            self.current_obj_idx = None;
+            self.last_pos = None;
+            *cache = Cache::default();
+        }
+
+        Ok(removed.len())
+    }
+
+    fn has_disasm(&self, obj_idx: usize) -> bool {
+        disasm_arch(self.arch_override, self.list[obj_idx].file.architecture()).is_some()
+    }
+
+    /// Disassembler for the arch of an object
+    fn disasm(&self, obj_idx: usize) -> anyhow::Result<capstone::Capstone> {
+        use capstone::arch::{ BuildsCapstone, BuildsCapstoneEndian, BuildsCapstoneExtraMode };
+
+        let obj = &self.list[obj_idx].file;
+        let arch = disasm_arch(self.arch_override, obj.architecture())
+            .with_context(|| format!("unsupport arch: {:?}", obj.architecture()))?;
+        let endian = match obj.endianness() {
+            object::Endianness::Little => capstone::Endian::Little,
+            object::Endianness::Big => capstone::Endian::Big
+        };
+
+        match arch {
+            DisasmArch::Aarch64 => capstone::Capstone::new()
+                .arm64()
+                .mode(capstone::arch::arm64::ArchMode::Arm)
+                .endian(endian)
+                .build(),
+            DisasmArch::X86_64 => capstone::Capstone::new()
+                .x86()
+                .mode(capstone::arch::x86::ArchMode::Mode64)
+                .build(),
+            DisasmArch::Arm => capstone::Capstone::new()
+                .arm()
+                .mode(capstone::arch::arm::ArchMode::Arm)
+                .endian(endian)
+                .build(),
+            DisasmArch::Thumb => capstone::Capstone::new()
+                .arm()
+                .mode(capstone::arch::arm::ArchMode::Thumb)
+                .endian(endian)
+                .build(),
+            DisasmArch::Riscv64 => capstone::Capstone::new()
+                .riscv()
+                .mode(capstone::arch::riscv::ArchMode::RiscV64)
+                .endian(endian)
+                .extra_mode(std::iter::once(capstone::arch::riscv::ArchExtraMode::RiscVC))
+                .build()
+        }
+            .map_err(|err| anyhow::format_err!("build capstone failed: {:?}", err))
+    }
+
+    fn display_name<'name>(&self, name: &'name str) -> Cow<'name, str> {
+        if self.demangle {
+            Cow::Owned(Demangle(name, self.swift).to_string())
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+
+    /// Object index by alias or name
+    fn find_obj(&self, name: &str) -> anyhow::Result<usize> {
+        self.select_obj(name, None)
+    }
+
+    /// Object index by alias, name, `ls` index or wildcard pattern
+    ///
+    /// If a pattern matches several objects, `idx` picks one of them,
+    /// otherwise they are listed and an error is returned.
+    fn select_obj(&self, name: &str, idx: Option<&str>) -> anyhow::Result<usize> {
+        let name = self.aliases.get(name)
+            .map(Vec::as_slice)
+            .unwrap_or(name.as_bytes());
+
+        let list_idx = name.to_str().ok()
+            .and_then(|name| name.parse::<usize>().ok());
+
+        if let Some(obj_idx) = self.list.iter().position(|obj| obj.name == name) {
+            if list_idx.filter(|&idx| idx != obj_idx && idx < self.list.len()).is_some() {
+                eprintln!("WARN: `{}` is both an object name and an index, use the name", name.as_bstr());
+            }
+            return Ok(obj_idx);
+        }
+
+        // index as printed by `ls`
+        if let Some(idx) = list_idx {
+            return if idx < self.list.len() {
+                Ok(idx)
+            } else {
+                anyhow::bail!("index too large")
+            };
+        }
+
+        if !name.contains(&b'*') && !name.contains(&b'?') {
+            anyhow::bail!("not found object");
+        }
+
+        let matched = self.list.iter()
+            .enumerate()
+            .filter(|(_, obj)| wildcard_match(name, &obj.name))
+            .map(|(obj_idx, _)| obj_idx)
+            .collect::<Vec<_>>();
+
+        match (matched.len(), idx) {
+            (0, _) => anyhow::bail!("not found object"),
+            (1, _) => Ok(matched[0]),
+            (_, Some(idx)) => {
+                let idx: usize = idx.parse().context("need index number")?;
+                matched.get(idx).copied().context("index too large")
+            },
+            (_, None) => {
+                for (idx, &obj_idx) in matched.iter().enumerate() {
+                    eprintln!("[{}] {}", idx, self.list[obj_idx].name.as_bstr());
+                }
+                anyhow::bail!("multiple objects matched");
+            }
+        }
+    }
+
+    fn get<'list>(&'list self, name: &str) -> anyhow::Result<&'list [SymbolPosition]> {
+        self.symbol_map.get(name)
+            .map(|syms| syms.as_slice())
+            .context("not found symbol")
+    }
+
+    fn index(&self, cache: &mut Cache<'buf>, pos: SymbolPosition) -> anyhow::Result<Symbol> {
+        cache.init_symmap(self)?;
+        self.index_cached(cache, pos)
+    }
+
+    /// Same as `index`, but requires `Cache::init_symmap` to be called first
+    fn index_cached(&self, cache: &Cache<'buf>, pos: SymbolPosition) -> anyhow::Result<Symbol> {
+        let sym = self.list[pos.obj_idx].symbol(pos)?;
+
+        let section_idx = match sym.section() {
+            object::read::SymbolSection::Section(idx) => idx,
+            object::read::SymbolSection::Undefined => anyhow::bail!("symbol is undefined"),
+            section => anyhow::bail!("bad section: {:?}", section)
+        };
+
+        let size = if self.list[pos.obj_idx].file.format() != object::BinaryFormat::MachO {
+            sym.size()
+        } else {
+            let symmap = &cache.symmap_list[pos.obj_idx];
+            let idx = match symmap.symbols()
+                .binary_search_by_key(&sym.address(), |sym| sym.address())
+            {
+                Ok(idx) => idx,
+                Err(_) => anyhow::bail!("not found symbol address")
+            };
+            match symmap.symbols().get(idx + 1) {
+                Some(next_addr) => next_addr.address() - sym.address(),
+                None => {
+                    let obj = &self.list[pos.obj_idx];
+                    let section = obj.file.section_by_index(section_idx)?;
+                    section.address() + section.size() - sym.address()
+                }
+            }
+        };
+
+        let kind = self.symbol_kind(pos);
+
+        Ok(Symbol {
+            pos, section_idx,
+            address: sym.address(),
+            size, kind
+        })
+    }
+
+    fn dump<'cache>(&self, cache: &'cache mut Cache<'buf>, sym: &Symbol)
+        -> anyhow::Result<DoubleLife<'cache, 'buf, [u8]>>
+    {
+        self.section_range(cache, sym.pos.obj_idx, sym.section_idx, sym.address, sym.size)
+    }
+
+    /// Bytes of `address..address + size` in a section, decompressed if needed
+    fn section_range<'cache>(
+        &self,
+        cache: &'cache mut Cache<'buf>,
+        obj_idx: usize,
+        section_idx: object::read::SectionIndex,
+        address: u64,
+        size: u64
+    )
+        -> anyhow::Result<DoubleLife<'cache, 'buf, [u8]>>
+    {
+        use std::collections::hash_map::Entry;
+
+        let cache_idx = (obj_idx, section_idx);
+
+        match cache.decompress_sections.entry(cache_idx) {
+            Entry::Occupied(entry) => {
+                let (section_addr, data) = entry.into_mut();
+                data_range(data, *section_addr, address, size)
+                    .map(DoubleLife::Left)
+            },
+            Entry::Vacant(entry) => {
+                let obj = &self.list[obj_idx];
+                let section = obj.file.section_by_index(section_idx)?;
+                let section_addr = section.address();
+
+                match section_data(&section)? {
+                    Cow::Borrowed(data) => data_range(data, section_addr, address, size)
+                        .map(DoubleLife::Right),
+                    Cow::Owned(data) => {
+                        let data = entry.insert((section_addr, data))
+                            .1
+                            .as_slice();
+                        data_range(data, section_addr, address, size)
+                            .map(DoubleLife::Left)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Disassemble a text symbol into `mnemonic op_str` lines, without addresses
+    fn disasm_text(&self, cache: &mut Cache<'buf>, sym: &Symbol) -> anyhow::Result<Vec<String>> {
+        let data = self.dump(cache, sym)?;
+        let disasm = self.disasm(sym.pos.obj_idx)?;
+        let insns = disasm.disasm_all(data.as_ref(), sym.address)
+            .map_err(|err| anyhow::format_err!("disasm failed: {:?}", err))?;
+
+        Ok(insns.iter()
+            .map(|ins| format!("{} {}",
+                ins.mnemonic().unwrap_or_default(),
+                ins.op_str().unwrap_or_default()
+            ).trim_end().to_string())
+            .collect())
+    }
+
+    fn reloc(&self, cache: &mut Cache<'buf>, sym: &Symbol)
+        -> anyhow::Result<Vec<Relocation>>
+    {
+        cache.init_reloc(self, sym.pos.obj_idx, sym.section_idx)?;
+
+        let relocs = cache.reloc_list[sym.pos.obj_idx]
+            .get(&sym.section_idx)
+            .unwrap();
+        let section = self.list[sym.pos.obj_idx].file.section_by_index(sym.section_idx)?;
+
+        let address = sym.address - section.address();
+        let start = relocs.partition_point(|(offset, _)| *offset < address);
+        let end = relocs.partition_point(|(offset, _)| *offset < address + sym.size);
+
+        relocs.get(start..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|(offset, reloc)| Relocation::new(*offset, reloc))
+            .collect()
+    }
+
+    /// Every relocation applied to a section
+    fn section_reloc(
+        &self,
+        cache: &mut Cache<'buf>,
+        obj_idx: usize,
+        section_idx: object::read::SectionIndex
+    )
+        -> anyhow::Result<Vec<Relocation>>
+    {
+        cache.init_reloc(self, obj_idx, section_idx)?;
+
+        cache.reloc_list[obj_idx]
+            .get(&section_idx)
+            .unwrap()
+            .iter()
+            .map(|(offset, reloc)| Relocation::new(*offset, reloc))
+            .collect()
+    }
+
+    /// Defined text symbol a branch relocation calls into
+    ///
+    /// Undefined targets are resolved by name against all loaded objects.
+    fn call_target(&self, obj_idx: usize, reloc: &Relocation) -> Option<SymbolPosition> {
+        use object::{ elf, Architecture, RelocationKind };
+
+        let file = &self.list[obj_idx].file;
+
+        match (reloc.kind, file.architecture()) {
+            (RelocationKind::Relative, _)
+                | (RelocationKind::PltRelative, _)
+                | (RelocationKind::MachO { relative: true, .. }, _) => (),
+            // branches that `object` doesn't give a generic kind
+            (RelocationKind::Elf(elf::R_ARM_CALL), Architecture::Arm)
+                | (RelocationKind::Elf(elf::R_ARM_JUMP24), Architecture::Arm)
+                | (RelocationKind::Elf(elf::R_ARM_THM_PC22), Architecture::Arm)
+                | (RelocationKind::Elf(elf::R_ARM_THM_JUMP24), Architecture::Arm)
+                | (RelocationKind::Elf(elf::R_AARCH64_CALL26), Architecture::Aarch64)
+                | (RelocationKind::Elf(elf::R_AARCH64_JUMP26), Architecture::Aarch64) => (),
+            _ => return None
+        }
+
+        let sym_idx = match reloc.target {
+            RelocationTarget::Symbol(idx) => idx,
+            RelocationTarget::Section(_) => return None
+        };
+        let sym = file.symbol_by_index(sym_idx).ok()?;
+        let name = sym.name().ok().filter(|name| !name.is_empty())?;
+
+        if sym.is_definition() {
+            return Some(SymbolPosition { obj_idx, sym_idx, dynamic: false })
+                .filter(|_| sym.kind() == object::SymbolKind::Text);
+        }
+
+        self.symbol_map.get(name)?
+            .iter()
+            .copied()
+            .find(|&pos| self.symbol_kind(pos) == 'T')
+    }
+
+    /// Global definition that is neither weak nor common
+    fn is_strong_definition(&self, pos: SymbolPosition) -> bool {
+        let sym = match self.list[pos.obj_idx].symbol(pos) {
+            Ok(sym) => sym,
+            Err(_) => return false
+        };
+
+        !sym.is_weak() && matches!(self.symbol_kind(pos), 'T' | 'D' | 'R' | 'B')
+    }
+
+    fn symbol_kind(&self, pos: SymbolPosition) -> char {
+        let obj = &self.list[pos.obj_idx];
+        let sym = obj.symbol(pos).unwrap();
+
+        symbol_kind(&obj.file, &sym)
+    }
+}
+
+/// Capstone arch for an object arch, `None` if unsupported
+fn disasm_arch(arch_override: Option<DisasmArch>, arch: object::Architecture) -> Option<DisasmArch> {
+    match (arch_override, arch) {
+        (Some(disasm_arch), _) => Some(disasm_arch),
+        (None, object::Architecture::Aarch64) => Some(DisasmArch::Aarch64),
+        (None, object::Architecture::X86_64) => Some(DisasmArch::X86_64),
+        (None, object::Architecture::Arm) => Some(DisasmArch::Arm),
+        (None, object::Architecture::Riscv64) => Some(DisasmArch::Riscv64),
+        _ => None
+    }
+}
+
+/// Fails if the object has another architecture or format, only warns with `--allow-mixed`
+fn check_consistent(
+    obj: &ObjectFile<'_>,
+    arch: object::Architecture,
+    format: object::BinaryFormat,
+    check_arch: bool,
+    allow_mixed: bool
+)
+    -> anyhow::Result<()>
+{
+    let msg = if check_arch && obj.file.architecture() != arch {
+        format!("inconsistent architecture: {:?} vs {:?} - {}", arch, obj.file.architecture(), obj.name.as_bstr())
+    } else if obj.file.format() != format {
+        format!("inconsistent format: {:?} vs {:?} - {}", format, obj.file.format(), obj.name.as_bstr())
+    } else {
+        return Ok(());
+    };
+
+    if allow_mixed {
+        eprintln!("WARN: {}", msg);
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg(msg))
+    }
+}
+
+fn build_symbol_map<'buf>(list: &[ObjectFile<'buf>])
+    -> anyhow::Result<IndexMap<&'buf str, Vec<SymbolPosition>>>
+{
+    let mut symbol_map: IndexMap<_, Vec<SymbolPosition>> = IndexMap::new();
+    let mut seen = HashSet::new();
+
+    for (idx, obj) in list.iter().enumerate() {
+        seen.clear();
+
+        for sym in obj.file.symbols() {
+            let sym_name = match sym.name() {
+                Ok(name) => name,
+                Err(err) => anyhow::bail!("bad symbol name: {:?}", err)
+            };
+            let pos = SymbolPosition {
+                obj_idx: idx,
+                sym_idx: sym.index(),
+                dynamic: false
+            };
+            seen.insert((sym_name, sym.address()));
+            symbol_map.entry(sym_name)
+                .or_default()
+                .push(pos);
+        }
+
+        // shared objects may only keep dynamic symbols
+        for sym in obj.file.dynamic_symbols() {
+            let sym_name = match sym.name() {
+                Ok(name) => name,
+                Err(err) => anyhow::bail!("bad symbol name: {:?}", err)
+            };
+
+            if seen.contains(&(sym_name, sym.address())) {
+                continue
+            }
+
+            let pos = SymbolPosition {
+                obj_idx: idx,
+                sym_idx: sym.index(),
+                dynamic: true
+            };
+            symbol_map.entry(sym_name)
+                .or_default()
+                .push(pos);
+        }
+    }
+    symbol_map.values_mut().for_each(|list| list.shrink_to_fit());
+    symbol_map.shrink_to_fit();
+
+    Ok(symbol_map)
+}
+
+/// Persisted `symbol_map`
+///
+/// Only symbol positions are stored, names are read back from the objects by index.
+/// The cache is valid as long as every input file keeps its path, size and mtime.
+struct IndexCache {
+    path: PathBuf,
+    stamp: Vec<u8>
+}
+
+const INDEX_CACHE_MAGIC: &[u8] = b"SYMIDX02";
+
+impl IndexCache {
+    /// Returns `None` if no path is given and there is no per-user cache directory
+    fn new(objects: &[PathBuf], path: Option<PathBuf>)
+        -> anyhow::Result<Option<IndexCache>>
+    {
+        use std::hash::{ Hash, Hasher };
+        use std::collections::hash_map::DefaultHasher;
+        use std::time::UNIX_EPOCH;
+
+        let mut stamp = Vec::new();
+        let mut hasher = DefaultHasher::new();
+
+        put_u64(&mut stamp, objects.len() as u64);
+        for path in objects {
+            let path = fs::canonicalize(path)
+                .with_context(|| format!("canonicalize {}", path.display()))?;
+            let metadata = fs::metadata(&path)
+                .with_context(|| format!("stat {}", path.display()))?;
+            let path = path.as_os_str().as_encoded_bytes();
+            let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+
+            path.hash(&mut hasher);
+            put_bytes(&mut stamp, path);
+            put_u64(&mut stamp, metadata.len());
+            put_u64(&mut stamp, mtime.as_secs());
+            put_u64(&mut stamp, mtime.subsec_nanos().into());
+        }
+
+        let path = match path {
+            Some(path) => path,
+            None => match cache_dir() {
+                Some(dir) => dir.join(format!("link-{:016x}.idx", hasher.finish())),
+                None => return Ok(None)
+            }
+        };
+
+        Ok(Some(IndexCache { path, stamp }))
+    }
+
+    fn load<'buf>(
+        &self,
+        list: &[ObjectFile<'buf>],
+        arch: object::Architecture,
+        format: object::BinaryFormat
+    )
+        -> anyhow::Result<Option<IndexMap<&'buf str, Vec<SymbolPosition>>>>
+    {
+        let buf = match fs::read(&self.path) {
+            Ok(buf) => buf,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into())
+        };
+        let mut buf = buf.as_slice();
+
+        if take_bytes(&mut buf, INDEX_CACHE_MAGIC.len())? != INDEX_CACHE_MAGIC
+            || get_bytes(&mut buf)? != self.stamp.as_slice()
+            || get_bytes(&mut buf)? != format!("{:?}", arch).as_bytes()
+            || get_bytes(&mut buf)? != format!("{:?}", format).as_bytes()
+            || get_u64(&mut buf)? != list.len() as u64
+        {
+            return Ok(None);
+        }
+
+        for obj in list {
+            if get_bytes(&mut buf)? != obj.name.as_ref() {
+                return Ok(None);
+            }
+        }
+
+        let count = get_u64(&mut buf)?.try_into()?;
+        let mut symbol_map = IndexMap::with_capacity(count);
+
+        for _ in 0..count {
+            let pos_count = get_u64(&mut buf)?.try_into()?;
+            let mut syms = Vec::with_capacity(pos_count);
+
+            for _ in 0..pos_count {
+                let obj_idx: usize = get_u64(&mut buf)?.try_into()?;
+                let sym_idx = object::read::SymbolIndex(get_u64(&mut buf)?.try_into()?);
+                let dynamic = get_u64(&mut buf)? != 0;
+                anyhow::ensure!(obj_idx < list.len(), "bad object index");
+                syms.push(SymbolPosition { obj_idx, sym_idx, dynamic });
+            }
+
+            let pos = syms.first().context("empty symbol position")?;
+            let name = list[pos.obj_idx].symbol(*pos)?.name()?;
+            symbol_map.insert(name, syms);
+        }
+
+        Ok(Some(symbol_map))
+    }
+
+    fn save(
+        &self,
+        list: &[ObjectFile<'_>],
+        arch: object::Architecture,
+        format: object::BinaryFormat,
+        symbol_map: &IndexMap<&str, Vec<SymbolPosition>>
+    )
+        -> anyhow::Result<()>
+    {
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(INDEX_CACHE_MAGIC);
+        put_bytes(&mut buf, &self.stamp);
+        put_bytes(&mut buf, format!("{:?}", arch).as_bytes());
+        put_bytes(&mut buf, format!("{:?}", format).as_bytes());
+        put_u64(&mut buf, list.len() as u64);
+        for obj in list {
+            put_bytes(&mut buf, &obj.name);
+        }
+
+        put_u64(&mut buf, symbol_map.len() as u64);
+        for syms in symbol_map.values() {
+            put_u64(&mut buf, syms.len() as u64);
+            for pos in syms {
+                put_u64(&mut buf, pos.obj_idx as u64);
+                put_u64(&mut buf, pos.sym_idx.0 as u64);
+                put_u64(&mut buf, pos.dynamic.into());
+            }
+        }
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let mut builder = fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder.create(dir)
+                .with_context(|| format!("create {}", dir.display()))?;
+        }
+
+        // write a fresh file and rename it over the cache,
+        // so an existing file or symlink at either path is never written through
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let result = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+            .with_context(|| format!("create {}", tmp_path.display()))?
+            .write_all(&buf)
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .with_context(|| format!("write {}", self.path.display()));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+}
+
+/// Per-user cache directory, `$XDG_CACHE_HOME/sym` or `~/.cache/sym`
+fn cache_dir() -> Option<PathBuf> {
+    let absolute = |name| std::env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute());
+
+    absolute("XDG_CACHE_HOME")
+        .or_else(|| absolute("HOME").map(|home| home.join(".cache")))
+        .map(|dir| dir.join("sym"))
+}
+
+fn put_u64(buf: &mut Vec<u8>, n: u64) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn take_bytes<'a>(buf: &mut &'a [u8], n: usize) -> anyhow::Result<&'a [u8]> {
+    anyhow::ensure!(buf.len() >= n, "unexpected end of index cache");
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+fn get_u64(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut n = [0; 8];
+    n.copy_from_slice(take_bytes(buf, 8)?);
+    Ok(u64::from_le_bytes(n))
+}
+
+fn get_bytes<'a>(buf: &mut &'a [u8]) -> anyhow::Result<&'a [u8]> {
+    let len = get_u64(buf)?.try_into()?;
+    take_bytes(buf, len)
+}
+
+impl<'buf> Cache<'buf> {
+    fn init_symmap(&mut self, explorer: &Explorer<'buf>) -> anyhow::Result<()> {
+        let has_macho = explorer.list.iter().any(|obj| obj.file.format() == object::BinaryFormat::MachO);
+
+        if !has_macho || !self.symmap_list.is_empty() {
+            return Ok(())
+        }
+
+        self.symmap_list = explorer.list.iter()
+            .map(|obj| obj.file.symbol_map())
+            .collect::<Vec<_>>();
+
+        Ok(())
+    }
+
+    fn version(&mut self, explorer: &Explorer<'buf>, pos: SymbolPosition)
+        -> anyhow::Result<Option<&'buf [u8]>>
+    {
+        if self.versions_list.is_empty() {
+            self.versions_list = explorer.list.iter()
+                .map(|obj| Ok([symbol_versions(&obj.file, false)?, symbol_versions(&obj.file, true)?]))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+        }
+
+        let versions = &self.versions_list[pos.obj_idx][usize::from(pos.dynamic)];
+        Ok(versions.get(&pos.sym_idx.0).copied())
+    }
+
+    fn init_demangled(&mut self, explorer: &Explorer<'buf>) {
+        use rayon::prelude::*;
+
+        if !self.demangled_names.is_empty() {
+            return
+        }
+
+        self.demangled_names = explorer.symbol_map.keys()
+            .collect::<Vec<_>>()
+            .par_iter()
+            .map(|name| Demangle(name, explorer.swift).to_string().into_boxed_str())
+            .collect();
+    }
+
+    /// Decompressed length of a section, keeping the data for later dumps
+    fn uncompressed_size(
+        &mut self,
+        explorer: &Explorer<'buf>,
+        obj_idx: usize,
+        section_idx: object::read::SectionIndex
+    )
+        -> anyhow::Result<u64>
+    {
+        if let Some((_, data)) = self.decompress_sections.get(&(obj_idx, section_idx)) {
+            return Ok(data.len() as u64);
+        }
+
+        let section = explorer.list[obj_idx].file.section_by_index(section_idx)?;
+        let section_addr = section.address();
+
+        match section_data(&section)? {
+            Cow::Borrowed(data) => Ok(data.len() as u64),
+            Cow::Owned(data) => {
+                let len = data.len() as u64;
+                self.decompress_sections.insert((obj_idx, section_idx), (section_addr, data));
+                Ok(len)
+            }
+        }
+    }
+
+    fn init_reloc(
+        &mut self,
+        explorer: &Explorer<'buf>,
+        obj_idx: usize,
+        section_idx: object::read::SectionIndex
+    )
+        -> anyhow::Result<()>
+    {
+        if self.reloc_list.is_empty() {
+            self.reloc_list = explorer
+                .list
+                .iter()
+                .map(|_| HashMap::new())
+                .collect::<Vec<_>>()
+        }
+
+        let obj = &explorer.list[obj_idx];
 
-            let sym = explorer.index(cache, pos)?;
-            let list = explorer.reloc(cache, &sym)?;
-            let obj = &explorer.list[sym.pos.obj_idx];
+        let map = &mut self.reloc_list[obj_idx];
+        let list = map.entry(section_idx).or_default();
 
-            let stdout = std::io::stdout();
-            let mut stdout = stdout.lock();
+        if list.is_empty() {
+            let section = obj.file.section_by_index(section_idx)?;
+            let mut data = None;
+
+            for (offset, mut reloc) in section.relocations() {
+                if reloc.has_implicit_addend()
+                    && matches!(reloc.kind(), object::RelocationKind::Absolute | object::RelocationKind::Relative)
+                {
+                    let data = match data.as_ref() {
+                        Some(data) => data,
+                        None => data.insert(section_data(&section)?)
+                    };
+                    if let Some(addend) = implicit_addend(data, offset, reloc.size(), obj.file.endianness()) {
+                        reloc.set_addend(reloc.addend().wrapping_add(addend));
+                    }
+                }
 
-            if !list.is_empty() {
-                writeln!(stdout, "OFFSET           ADDEND               TYPE    ADDRESS          NAME")?;
+                list.push((offset, reloc));
             }
 
-            for reloc in &list {
-                let (ty, addr, name) = match &reloc.target {
-                    RelocationTarget::Symbol(idx) => {
-                        let sym = obj.file.symbol_by_index(*idx)?;
-                        let addr = sym.address();
-                        let name = sym.name()?.to_string();
-                        ("symbol", addr, name)
-                    },
-                    RelocationTarget::Section(idx) => {
-                        let section = obj.file.section_by_index(*idx)?;
-                        let addr = section.address();
-                        let name = section.name()?.to_string();
-                        ("section", addr, name)
-                    }
-                };
+            list.sort_by_key(|(offset, _)| *offset);
+            list.shrink_to_fit();
+        }
 
-                writeln!(stdout,
-                    "{:016x} {:<20} {:<7} {:016x} {}",
-                    reloc.offset,
-                    reloc.addend,
-                    ty,
-                    addr,
-                    name
-                )?;
-            }
-        },
-        Some(cmd) if !cmd.trim().is_empty() => anyhow::bail!("unknown command"),
-        _ => ()
+        Ok(())
     }
-
-    Ok(())
 }
 
-struct Explorer<'a, 'buf> {
-    list: &'a [ObjectFile<'buf>],
-    #[allow(dead_code)] arch: object::Architecture,
-    format: object::BinaryFormat,
-    disasm: fn() -> anyhow::Result<capstone::Capstone>,
-    symbol_map: IndexMap<&'buf str, Vec<SymbolPosition>>,
-    current_obj_idx: Option<usize>
-}
+/// Groups and implicitly read/written registers of an instruction
+fn print_insn_detail(stdout: &mut dyn Write, disasm: &capstone::Capstone, ins: &capstone::Insn<'_>)
+    -> anyhow::Result<()>
+{
+    let detail = disasm.insn_detail(ins)
+        .map_err(|err| anyhow::format_err!("get detail failed: {:?}", err))?;
+
+    let groups = detail.groups().iter()
+        .filter_map(|&id| disasm.group_name(id))
+        .collect::<Vec<_>>();
+    let reads = detail.regs_read().iter()
+        .filter_map(|&id| disasm.reg_name(id))
+        .collect::<Vec<_>>();
+    let writes = detail.regs_write().iter()
+        .filter_map(|&id| disasm.reg_name(id))
+        .collect::<Vec<_>>();
+
+    let mut parts = Vec::new();
+    if !groups.is_empty() {
+        parts.push(format!("groups: {}", groups.join(", ")));
+    }
+    if !reads.is_empty() {
+        parts.push(format!("reads: {}", reads.join(", ")));
+    }
+    if !writes.is_empty() {
+        parts.push(format!("writes: {}", writes.join(", ")));
+    }
 
-#[derive(Default)]
-struct Cache<'buf> {
-    symmap_list: Vec<object::read::SymbolMap<object::read::SymbolMapName<'buf>>>,
-    decompress_sections: HashMap<(usize, object::read::SectionIndex), (u64, Vec<u8>)>,
-    reloc_list: Vec<HashMap<object::read::SectionIndex, Vec<(u64, object::read::Relocation)>>>
-}
+    if !parts.is_empty() {
+        writeln!(stdout, "\t; {}", parts.join("; "))?;
+    }
 
-#[derive(Clone, Copy)]
-struct SymbolPosition {
-    obj_idx: usize,
-    sym_idx: object::read::SymbolIndex
+    Ok(())
 }
 
-struct Symbol {
-    pos: SymbolPosition,
-    section_idx: object::read::SectionIndex,
-    address: u64,
-    size: u64,
-    kind: char
+/// Instruction line, optionally with raw bytes and ANSI colors for mnemonic,
+/// registers and immediates
+struct InsnDisplay<'a> {
+    ins: &'a capstone::Insn<'a>,
+    color: bool,
+    /// pad address and raw bytes to these widths, if showing bytes
+    bytes_width: Option<(usize, usize)>
 }
 
-#[derive(Debug)]
-struct Relocation {
-    offset: u64,
-    target: RelocationTarget,
-    addend: i64
-}
+impl std::fmt::Display for InsnDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        const MNEMONIC: &str = "\x1b[32m";
+        const REGISTER: &str = "\x1b[36m";
+        const IMMEDIATE: &str = "\x1b[33m";
+        const RESET: &str = "\x1b[0m";
 
-#[derive(Debug)]
-enum RelocationTarget {
-    Symbol(object::read::SymbolIndex),
-    Section(object::read::SectionIndex)
-}
+        // operand words that are neither registers nor immediates
+        const KEYWORDS: &[&str] = &[
+            "byte", "word", "dword", "qword", "tbyte", "xmmword", "ymmword", "zmmword", "ptr",
+            "lsl", "lsr", "asr", "ror", "sxtw", "uxtw", "sxtx", "uxtx"
+        ];
 
-impl<'a, 'buf> Explorer<'a, 'buf> {
-    fn build(list: &'a [ObjectFile<'buf>]) -> anyhow::Result<Explorer<'a, 'buf>> {
-        use capstone::arch::BuildsCapstone;
+        let InsnDisplay { ins, color, bytes_width } = *self;
 
-        let (arch, format) = {
-            let obj = list.iter().next().context("not found object")?;
-            let arch = obj.file.architecture();
-            let format = obj.file.format();
+        if let Some((addr_width, width)) = bytes_width {
+            write!(f, "{:<1$} ", format!("{:#x}:", ins.address()), addr_width + 1)?;
 
-            if let Some(obj) = list.iter().find(|obj| obj.file.architecture() != arch) {
-                anyhow::bail!("inconsistent architecture: {:?} vs {:?} - {}",
-                    arch,
-                    obj.file.architecture(),
-                    obj.name.as_bstr()
-                );
+            let bytes = ins.bytes();
+            for b in bytes {
+                write!(f, "{:02x} ", b)?;
             }
-
-            if let Some(obj) = list.iter().find(|obj| obj.file.format() != format) {
-                anyhow::bail!("inconsistent format: {:?} vs {:?} - {}",
-                    format,
-                    obj.file.format(),
-                    obj.name.as_bstr()
-                );
+            for _ in bytes.len()..width {
+                f.write_str("   ")?;
             }
+            f.write_str(" ")?;
+        } else {
+            write!(f, "{:#x}: ", ins.address())?;
+        }
 
-            (arch, format)
+        let mnemonic = match ins.mnemonic() {
+            Some(mnemonic) => mnemonic,
+            None => return Ok(())
         };
 
-        let disasm = match arch {
-            object::Architecture::Aarch64 => || {
-                capstone::Capstone::new()
-                    .arm64()
-                    .mode(capstone::arch::arm64::ArchMode::Arm)
-                    .build()
-                    .map_err(|err| anyhow::format_err!("build capstone failed: {:?}", err))
-            },
-            object::Architecture::X86_64 => || {
-                capstone::Capstone::new()
-                    .x86()
-                    .mode(capstone::arch::x86::ArchMode::Mode64)
-                    .build()
-                    .map_err(|err| anyhow::format_err!("build capstone failed: {:?}", err))
-            },
-            _ => anyhow::bail!("unsupport arch: {:?}", arch)
+        if !color {
+            return write!(f, "{} {}", mnemonic, ins.op_str().unwrap_or_default());
+        }
+
+        write!(f, "{}{}{} ", MNEMONIC, mnemonic, RESET)?;
+
+        let op_str = match ins.op_str() {
+            Some(op_str) => op_str,
+            None => return Ok(())
         };
 
-        let mut symbol_map: IndexMap<_, Vec<SymbolPosition>> = IndexMap::new();
-        for (idx, obj) in list.iter().enumerate() {
-            for sym in obj.file.symbols() {
-                let sym_name = match sym.name() {
-                    Ok(name) => name,
-                    Err(err) => anyhow::bail!("bad symbol name: {:?}", err)
-                };
-                let pos = SymbolPosition {
-                    obj_idx: idx,
-                    sym_idx: sym.index()
-                };
-                symbol_map.entry(sym_name)
-                    .or_default()
-                    .push(pos);
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        let mut rest = op_str;
+
+        while let Some(c) = rest.chars().next() {
+            let is_immediate = c.is_ascii_digit() || c == '#';
+
+            if !is_immediate && !is_word(c) {
+                write!(f, "{}", c)?;
+                rest = &rest[c.len_utf8()..];
+                continue
+            }
+
+            let len = rest[1..].find(|c| !is_word(c)).map(|n| n + 1).unwrap_or(rest.len());
+            let (word, next) = rest.split_at(len);
+
+            if is_immediate {
+                write!(f, "{}{}{}", IMMEDIATE, word, RESET)?;
+            } else if KEYWORDS.contains(&word) {
+                write!(f, "{}", word)?;
+            } else {
+                write!(f, "{}{}{}", REGISTER, word, RESET)?;
             }
+
+            rest = next;
         }
-        symbol_map.values_mut().for_each(|list| list.shrink_to_fit());
-        symbol_map.shrink_to_fit();
 
-        Ok(Explorer {
-            list, arch, format, disasm,
-            symbol_map,
-            current_obj_idx: None
-        })
+        Ok(())
     }
+}
 
-    fn get<'list>(&'list self, name: &str) -> anyhow::Result<&'list [SymbolPosition]> {
-        self.symbol_map.get(name)
-            .map(|syms| syms.as_slice())
-            .context("not found symbol")
+fn print_callgraph<'buf>(
+    stdout: &mut dyn Write,
+    explorer: &Explorer<'buf>,
+    cache: &mut Cache<'buf>,
+    pos: SymbolPosition,
+    depth: usize,
+    path: &mut Vec<SymbolPosition>
+)
+    -> anyhow::Result<()>
+{
+    let obj = &explorer.list[pos.obj_idx];
+    let name = obj.symbol(pos)?.name()?;
+    let indent = path.len() * 2;
+
+    if path.contains(&pos) {
+        writeln!(stdout, "{:indent$}{} (cycle)", "", explorer.display_name(name), indent = indent)?;
+        return Ok(());
     }
 
-    fn index(&self, cache: &mut Cache<'buf>, pos: SymbolPosition) -> anyhow::Result<Symbol> {
-        let sym = self.list[pos.obj_idx].file.symbol_by_index(pos.sym_idx)?;
+    writeln!(stdout, "{:indent$}{}", "", explorer.display_name(name), indent = indent)?;
 
-        let section_idx = match sym.section() {
-            object::read::SymbolSection::Section(idx) => idx,
-            object::read::SymbolSection::Undefined => anyhow::bail!("symbol is undefined"),
-            section => anyhow::bail!("bad section: {:?}", section)
-        };
+    if path.len() >= depth {
+        return Ok(());
+    }
 
-        let size = if self.format != object::BinaryFormat::MachO {
-            sym.size()
-        } else {
-            cache.init_symmap(self)?;
+    let sym = explorer.index(cache, pos)?;
+    let mut callees = Vec::new();
 
-            let symmap = &cache.symmap_list[pos.obj_idx];
-            let idx = match symmap.symbols()
-                .binary_search_by_key(&sym.address(), |sym| sym.address())
-            {
-                Ok(idx) => idx,
-                Err(_) => anyhow::bail!("not found symbol address")
-            };
-            match symmap.symbols().get(idx + 1) {
-                Some(next_addr) => next_addr.address() - sym.address(),
-                None => {
-                    let obj = &self.list[pos.obj_idx];
-                    let section = obj.file.section_by_index(section_idx)?;
-                    section.address() + section.size() - sym.address()
-                }
+    for reloc in explorer.reloc(cache, &sym)? {
+        if let Some(target) = explorer.call_target(pos.obj_idx, &reloc) {
+            if !callees.contains(&target) {
+                callees.push(target);
             }
-        };
+        }
+    }
 
-        let kind = self.symbol_kind(pos);
+    path.push(pos);
+    for target in callees {
+        print_callgraph(stdout, explorer, cache, target, depth, path)?;
+    }
+    path.pop();
 
-        Ok(Symbol {
-            pos, section_idx,
-            address: sym.address(),
-            size, kind
-        })
+    Ok(())
+}
+
+fn print_reloc_table(
+    stdout: &mut dyn Write,
+    explorer: &Explorer<'_>,
+    obj: &ObjectFile<'_>,
+    list: &[Relocation],
+    raw: bool,
+    hex: bool
+)
+    -> anyhow::Result<()>
+{
+    if !list.is_empty() {
+        writeln!(stdout, "OFFSET           ADDEND               TYPE    KIND         ADDRESS          NAME")?;
     }
 
-    fn dump<'cache>(&self, cache: &'cache mut Cache<'buf>, sym: &Symbol)
-        -> anyhow::Result<DoubleLife<'cache, 'buf, [u8]>>
-    {
-        use std::collections::hash_map::Entry;
+    for reloc in list {
+        let (ty, addr, name) = reloc.resolve_target(obj)?;
+        let name = if raw { Cow::Borrowed(name.as_str()) } else { explorer.display_name(&name) };
 
-        let cache_idx = (sym.pos.obj_idx, sym.section_idx);
+        let addend = if hex {
+            let sign = if reloc.addend < 0 { "-" } else { "" };
+            format!("{}{:#x}", sign, reloc.addend.unsigned_abs())
+        } else {
+            reloc.addend.to_string()
+        };
 
-        match cache.decompress_sections.entry(cache_idx) {
-            Entry::Occupied(entry) => {
-                let (section_addr, data) = entry.into_mut();
-                data_range(data, *section_addr, sym.address, sym.size)
-                    .map(DoubleLife::Left)
-            },
-            Entry::Vacant(entry) => {
-                let obj = &self.list[sym.pos.obj_idx];
-                let section = obj.file.section_by_index(sym.section_idx)?;
-                let section_addr = section.address();
+        writeln!(stdout,
+            "{:016x} {:<20} {:<7} {:<12} {:016x} {}",
+            reloc.offset,
+            addend,
+            ty,
+            reloc.kind_name(),
+            addr,
+            name
+        )?;
+    }
 
-                match section.uncompressed_data()? {
-                    Cow::Borrowed(data) => data_range(data, section_addr, sym.address, sym.size)
-                        .map(DoubleLife::Right),
-                    Cow::Owned(data) => {
-                        let data = entry.insert((section_addr, data))
-                            .1
-                            .as_slice();
-                        data_range(data, section_addr, sym.address, sym.size)
-                            .map(DoubleLife::Left)
-                    }
-                }
-            }
+    Ok(())
+}
+
+/// Relocation count by kind, most frequent first
+fn print_reloc_kinds(stdout: &mut dyn Write, kinds: &BTreeMap<String, usize>) -> anyhow::Result<()> {
+    let mut kinds = kinds.iter().collect::<Vec<_>>();
+    kinds.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+
+    writeln!(stdout, "relocations: {}", kinds.iter().map(|(_, count)| *count).sum::<usize>())?;
+    for (kind, count) in kinds {
+        writeln!(stdout, "  {:<12} {}", kind, count)?;
+    }
+
+    Ok(())
+}
+
+fn print_dump_json(
+    stdout: &mut dyn Write,
+    explorer: &Explorer<'_>,
+    sym: &Symbol,
+    name: &str,
+    address: u64,
+    data: &[u8]
+)
+    -> anyhow::Result<()>
+{
+    let obj = &explorer.list[sym.pos.obj_idx];
+    let section = obj.file.section_by_index(sym.section_idx)?;
+
+    writeln!(stdout, "{{")?;
+    writeln!(stdout, "  \"address\": {},", address)?;
+    writeln!(stdout, "  \"kind\": \"{}\",", sym.kind)?;
+    writeln!(stdout, "  \"size\": {},", sym.size)?;
+    writeln!(stdout, "  \"name\": {},", JsonStr(&explorer.display_name(name)))?;
+    writeln!(stdout, "  \"object\": {},", JsonStr(&obj.name.to_str_lossy()))?;
+    writeln!(stdout, "  \"section\": {},", JsonStr(section.name()?))?;
+    write!(stdout, "  \"bytes\": \"{}\"", HexStr(data))?;
+
+    if matches!(sym.kind, 't' | 'T') && explorer.has_disasm(sym.pos.obj_idx) {
+        let disasm = explorer.disasm(sym.pos.obj_idx)?;
+        let insns = disasm.disasm_all(data, address)
+            .map_err(|err| anyhow::format_err!("disasm failed: {:?}", err))?;
+
+        writeln!(stdout, ",")?;
+        writeln!(stdout, "  \"instructions\": [")?;
+        for (i, ins) in insns.iter().enumerate() {
+            writeln!(stdout,
+                "    {{\"address\":{},\"bytes\":\"{}\",\"mnemonic\":{},\"operands\":{}}}{}",
+                ins.address(),
+                HexStr(ins.bytes()),
+                JsonStr(ins.mnemonic().unwrap_or_default()),
+                JsonStr(ins.op_str().unwrap_or_default()),
+                if i + 1 < insns.len() { "," } else { "" }
+            )?;
         }
+        writeln!(stdout, "  ]")?;
+    } else {
+        writeln!(stdout)?;
     }
 
-    fn reloc<'cache>(&self, cache: &'cache mut Cache<'buf>, sym: &Symbol)
-        -> anyhow::Result<Vec<Relocation>>
-    {
-        cache.init_reloc(self, &sym)?;
+    writeln!(stdout, "}}")?;
 
-        let relocs = cache.reloc_list[sym.pos.obj_idx]
-            .get(&sym.section_idx)
-            .unwrap();
-        let section = self.list[sym.pos.obj_idx].file.section_by_index(sym.section_idx)?;
+    Ok(())
+}
 
-        let address = sym.address - section.address();
-        let start = relocs.partition_point(|(offset, _)| *offset < address);
-        let end = relocs.partition_point(|(offset, _)| *offset < address + sym.size);
+/// Symbol name as a file name, anything but ASCII alphanumerics, `_`, `.` and `-` becomes `_`
+///
+/// Long names are cut to stay under common file name limits.
+fn sanitize_file_name(name: &str) -> String {
+    const MAX_LEN: usize = 200;
 
-        let mut list = Vec::new();
-        for (offset, reloc) in relocs.get(start..end).unwrap_or_default() {
-            list.push(Relocation {
-                offset: *offset,
-                target: match reloc.target() {
-                    object::read::RelocationTarget::Symbol(idx) => RelocationTarget::Symbol(idx),
-                    object::read::RelocationTarget::Section(idx) => RelocationTarget::Section(idx),
-                    _ => anyhow::bail!("not support target: {:?}", reloc)
-                },
-                addend: reloc.addend()
-            });
-        }
+    let name = name.trim_start_matches('.');
+    let mut file_name = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') { c } else { '_' })
+        .take(MAX_LEN)
+        .collect::<String>();
 
-        Ok(list)
+    if file_name.is_empty() {
+        file_name.push('_');
     }
 
-    fn symbol_kind(&self, pos: SymbolPosition) -> char {
-        use object::{ SymbolSection, SectionKind };
+    file_name
+}
 
-        let obj = &self.list[pos.obj_idx];
-        let sym = obj.file.symbol_by_index(pos.sym_idx).unwrap();
-
-        let mut kind = match sym.section() {
-            SymbolSection::Undefined => 'U',
-            SymbolSection::Absolute => 'A',
-            SymbolSection::Common => 'C',
-            SymbolSection::Section(idx) => match obj.file.section_by_index(idx).map(|section| section.kind()) {
-                Ok(SectionKind::Text) => 't',
-                Ok(SectionKind::Data) | Ok(SectionKind::Tls) | Ok(SectionKind::TlsVariables) => {
-                    'd'
-                }
-                Ok(SectionKind::ReadOnlyData) | Ok(SectionKind::ReadOnlyString) => 'r',
-                Ok(SectionKind::UninitializedData) | Ok(SectionKind::UninitializedTls) => 'b',
-                Ok(SectionKind::Common) => 'C',
-                _ => '?',
-            },
-            _ => '?',
-        };
+/// Legacy rust names end with `::h` and a 16 digit hash
+fn strip_legacy_hash(name: &[u8]) -> &[u8] {
+    name.len().checked_sub(19)
+        .filter(|&n| name[n..].starts_with(b"::h"))
+        .map(|n| &name[..n])
+        .unwrap_or(name)
+}
 
-        if sym.is_global() {
-            kind = kind.to_ascii_uppercase();
+/// Split a demangled name on `::`, except inside generic arguments
+fn split_path(name: &str) -> Vec<&str> {
+    let bytes = name.as_bytes();
+    let mut path = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'<' => depth += 1,
+            // skip `->` of fn types
+            b'>' if i == 0 || bytes[i - 1] != b'-' => depth -= 1,
+            b':' if depth == 0 && bytes.get(i + 1) == Some(&b':') => {
+                path.push(&name[start..i]);
+                start = i + 2;
+                i += 1;
+            },
+            _ => ()
         }
-
-        kind
+        i += 1;
     }
+    path.push(&name[start..]);
+
+    path
 }
 
-impl<'buf> Cache<'buf> {
-    fn init_symmap(&mut self, explorer: &Explorer<'_, 'buf>) -> anyhow::Result<()> {
-        if explorer.format != object::BinaryFormat::MachO || !self.symmap_list.is_empty() {
-            return Ok(())
+/// Addend stored in the relocated field, for REL style relocations
+///
+/// Only plain fields are decoded, instruction encodings are left as is.
+fn implicit_addend(data: &[u8], offset: u64, size: u8, endian: object::Endianness) -> Option<i64> {
+    let len = match size {
+        8 | 16 | 32 | 64 => usize::from(size / 8),
+        _ => return None
+    };
+    let start: usize = offset.try_into().ok()?;
+    let field = data.get(start..start.checked_add(len)?)?;
+
+    let mut buf = [0; 8];
+    let value = match endian {
+        object::Endianness::Little => {
+            buf[..len].copy_from_slice(field);
+            u64::from_le_bytes(buf)
+        },
+        object::Endianness::Big => {
+            buf[8 - len..].copy_from_slice(field);
+            u64::from_be_bytes(buf)
         }
+    };
 
-        self.symmap_list = explorer.list.iter()
-            .map(|obj| obj.file.symbol_map())
-            .collect::<Vec<_>>();
+    // sign extend
+    let shift = 64 - u32::from(size);
+    Some((value << shift) as i64 >> shift)
+}
 
-        Ok(())
-    }
+fn print_c_array(stdout: &mut dyn Write, name: &str, data: &[u8]) -> anyhow::Result<()> {
+    let ident = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
 
-    fn init_reloc(&mut self, explorer: &Explorer<'_, 'buf>, sym: &Symbol)
-        -> anyhow::Result<()>
-    {
-        if self.reloc_list.is_empty() {
-            self.reloc_list = explorer
-                .list
-                .iter()
-                .map(|_| HashMap::new())
-                .collect::<Vec<_>>()
-        }
+    writeln!(stdout, "const uint8_t {}[{}] = {{", ident, data.len())?;
+    for chunk in data.chunks(12) {
+        let line = chunk.iter()
+            .map(|b| format!("0x{:02x},", b))
+            .collect::<Vec<_>>();
+        writeln!(stdout, "    {}", line.join(" "))?;
+    }
+    writeln!(stdout, "}};")?;
 
-        let obj = &explorer.list[sym.pos.obj_idx];
+    Ok(())
+}
 
-        let map = &mut self.reloc_list[sym.pos.obj_idx];
-        let list = map.entry(sym.section_idx).or_default();
+fn print_search_match(
+    stdout: &mut dyn Write,
+    explorer: &Explorer<'_>,
+    mangled_name: &str,
+    pos: SymbolPosition,
+    section: Option<&str>,
+    hash: Option<HashColumn>
+)
+    -> anyhow::Result<()>
+{
+    let obj = &explorer.list[pos.obj_idx];
+    let sym = obj.symbol(pos)?;
+    let kind = explorer.symbol_kind(pos);
+
+    write!(stdout, "{:016x} {} {} @ {:?}{}{}{}",
+        sym.address(),
+        kind,
+        mangled_name,
+        obj.name.as_bstr(),
+        if section.is_some() { "/" } else { "" },
+        section.unwrap_or_default(),
+        if pos.dynamic { " (dynamic)" } else { "" }
+    )?;
+
+    match hash {
+        Some(hash) => writeln!(stdout, "\t{}", hash)?,
+        None => writeln!(stdout)?
+    }
 
-        if list.is_empty() {
-            let section = obj.file.section_by_index(sym.section_idx)?;
+    Ok(())
+}
 
-            for (offset, reloc) in section.relocations() {
-                list.push((offset, reloc));
-            }
+/// `--hash` column, `-` for symbols without bytes
+struct HashColumn(Option<[u8; 32]>);
 
-            list.sort_by_key(|(offset, _)| *offset);
-            list.shrink_to_fit();
+impl std::fmt::Display for HashColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.0 {
+            Some(hash) => write!(f, "{}", HexStr(hash)),
+            None => f.write_str("-")
         }
+    }
+}
 
-        Ok(())
+/// SHA-256 of a symbol's bytes
+///
+/// `None` for symbols without bytes in the file: undefined, common and absolute
+/// symbols, and those in bss-like sections.
+fn content_hash<'buf>(explorer: &Explorer<'buf>, cache: &mut Cache<'buf>, pos: SymbolPosition)
+    -> anyhow::Result<Option<[u8; 32]>>
+{
+    let obj = &explorer.list[pos.obj_idx];
+    let section_idx = match obj.symbol(pos)?.section() {
+        object::read::SymbolSection::Section(idx) => idx,
+        _ => return Ok(None)
+    };
+
+    let kind = obj.file.section_by_index(section_idx)?.kind();
+    if matches!(kind, object::SectionKind::UninitializedData | object::SectionKind::UninitializedTls) {
+        return Ok(None);
     }
+
+    let sym = explorer.index(cache, pos)?;
+    let data = explorer.dump(cache, &sym)?;
+    let mut hasher = Sha256::default();
+    hasher.update(data.as_ref());
+
+    Ok(Some(hasher.finish()))
 }
 
 fn print_syms_list(
-    explorer: &Explorer<'_, '_>,
+    explorer: &Explorer<'_>,
     syms: &[SymbolPosition]
 ) -> anyhow::Result<()> {
     for (idx, &pos) in syms.iter()
         .enumerate()
     {
-        let name = &explorer.list[pos.obj_idx].name;
+        let obj = &explorer.list[pos.obj_idx];
+        let size = obj.symbol(pos)?.size();
         let kind = explorer.symbol_kind(pos);
-        eprintln!("[{}] {} by {:?}", idx, kind, name.as_bstr());
+        eprintln!("[{}] {} {} by {:?}", idx, kind, size, obj.name.as_bstr());
     }
 
     Ok(())
 }
 
-fn select(explorer: &Explorer<'_, '_>, syms: &[SymbolPosition], iter: Option<&str>)
+/// A symbol name with several definitions was given without an index
+#[derive(Debug)]
+struct AmbiguousSymbol(usize);
+
+impl std::fmt::Display for AmbiguousSymbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} definitions, re-run with an index", self.0)
+    }
+}
+
+impl std::error::Error for AmbiguousSymbol {}
+
+/// Resolve `<name> [idx]` arguments, `_` or no name reuses the last selected symbol
+fn select_symbol<'a, I>(explorer: &mut Explorer<'_>, iter: &mut I)
+    -> anyhow::Result<SymbolPosition>
+where
+    I: Iterator<Item = &'a str>
+{
+    let pos = match iter.next() {
+        None | Some("_") => explorer.last_pos.context("no symbol selected yet")?,
+        Some(name) => {
+            let syms = explorer.get(name)?;
+
+            match select(explorer, syms, iter.next())? {
+                Some(pos) => pos,
+                None => {
+                    let err = AmbiguousSymbol(syms.len());
+                    eprintln!("{}", err);
+                    print_syms_list(explorer, syms)?;
+                    return Err(err.into());
+                }
+            }
+        }
+    };
+
+    explorer.last_pos = Some(pos);
+
+    Ok(pos)
+}
+
+fn select(explorer: &Explorer<'_>, syms: &[SymbolPosition], iter: Option<&str>)
     -> anyhow::Result<Option<SymbolPosition>>
 {
     assert!(!syms.is_empty());
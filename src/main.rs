@@ -3,12 +3,30 @@ mod search;
 mod diff;
 mod contains;
 mod link;
+mod bloat;
 
+#[cfg(test)]
+mod tests;
+
+use std::io::Write;
 use argh::FromArgs;
 
 /// Cross-platform Symbol Tools
 #[derive(FromArgs, Debug)]
+#[argh(note = "Flags can be defaulted with environment variables: SYMBOL_TOOLS_SORT, \
+SYMBOL_TOOLS_NO_DEMANGLE, SYMBOL_TOOLS_SWIFT and SYMBOL_TOOLS_NO_MMAP take `1`, \
+SYMBOL_TOOLS_ADDR_WIDTH takes a number. A value given on the command line, like \
+--addr-width, takes precedence over the environment. Switches only add up: one set in \
+the environment can't be turned off on the command line.")]
 struct Options {
+    /// read files into memory instead of mmap
+    #[argh(switch)]
+    no_mmap: bool,
+
+    /// print time spent in each phase to stderr
+    #[argh(switch)]
+    timings: bool,
+
     #[argh(subcommand)]
     command: Command
 }
@@ -19,16 +37,46 @@ enum Command {
     Search(search::Options),
     Diff(diff::Options),
     Contains(contains::Options),
-    Link(link::Options)
+    Link(link::Options),
+    Bloat(bloat::Options)
 }
 
 fn main() -> anyhow::Result<()> {
-    let options: Options = argh::from_env();
+    let mut options: Options = argh::from_env();
+    options.apply_env()?;
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    run(options, &mut stdout)
+}
+
+impl Options {
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.no_mmap |= common::env_switch("NO_MMAP");
+
+        match &mut self.command {
+            Command::Search(cmd) => cmd.apply_env(),
+            Command::Diff(cmd) => cmd.apply_env(),
+            Command::Contains(cmd) => cmd.apply_env(),
+            Command::Link(cmd) => cmd.apply_env(),
+            Command::Bloat(_) => Ok(())
+        }
+    }
+}
+
+/// Run a subcommand, writing its results to `stdout`
+///
+/// The explorer is interactive and keeps using the real stdout.
+fn run(options: Options, stdout: &mut dyn Write) -> anyhow::Result<()> {
+    let mmap = !options.no_mmap;
+    let timings = common::Timings::new(options.timings);
 
     match options.command {
-        Command::Search(cmd) => cmd.exec(),
-        Command::Diff(cmd) => cmd.exec(),
-        Command::Contains(cmd) => cmd.exec(),
-        Command::Link(cmd) => cmd.exec()
+        Command::Search(cmd) => cmd.exec(mmap, timings, stdout),
+        Command::Diff(cmd) => cmd.exec(mmap, timings, stdout),
+        Command::Contains(cmd) => cmd.exec(mmap, timings, stdout),
+        Command::Link(cmd) => cmd.exec(mmap, timings),
+        Command::Bloat(cmd) => cmd.exec(mmap, timings, stdout)
     }
 }
@@ -1,13 +1,16 @@
 use std::fs;
-use std::io::{ self, Write };
+use std::io::Write;
 use std::path::PathBuf;
+use std::collections::{ HashMap, BTreeMap };
 use anyhow::Context;
 use aho_corasick::AhoCorasick;
 use bstr::ByteSlice;
-use memmap2::Mmap;
-use object::{ Object, Symbol, SymbolKind, ObjectSymbolTable, ObjectSymbol };
-use rustc_demangle::demangle;
+use object::{ Object, ObjectSection, Symbol, SymbolKind, ObjectSymbolTable, ObjectSymbol };
 use argh::FromArgs;
+use crate::common::{
+    AddrWidth, Address, Demangle, SchemeColumn, Timings,
+    env_option, env_switch, load_file, mangling_scheme, symbol_kind, symbol_table, symbol_versions
+};
 
 
 /// Cross-platform Symbol Searcher
@@ -22,6 +25,10 @@ pub struct Options {
     #[argh(positional)]
     keywords: Vec<String>,
 
+    /// read more keywords from a file, one per line, `#` starts a comment line
+    #[argh(option)]
+    keywords_file: Option<PathBuf>,
+
     /// print not demangle symbol
     #[argh(switch)]
     no_demangle: bool,
@@ -29,24 +36,74 @@ pub struct Options {
     /// sort by size
     #[argh(switch)]
     sort: bool,
+
+    /// require all keywords to match
+    #[argh(switch)]
+    all: bool,
+
+    /// print mangling scheme column
+    #[argh(switch)]
+    lang: bool,
+
+    /// use dynamic symbol table
+    #[argh(switch)]
+    dynamic: bool,
+
+    /// address digits, 8 or 16 (default)
+    #[argh(option)]
+    addr_width: Option<AddrWidth>,
+
+    /// only match instantiations of a generic path, eg `alloc::vec::Vec`
+    #[argh(option)]
+    generic: Option<String>,
+
+    /// demangle swift symbols
+    #[argh(switch)]
+    swift: bool,
+
+    /// print in nm format, including undefined and data symbols
+    #[argh(switch)]
+    nm: bool,
+
+    /// skip undefined symbols, their address and size are 0
+    #[argh(switch)]
+    no_undefined: bool,
+
+    /// append ELF symbol versions as `name@version`
+    #[argh(switch)]
+    versions: bool,
+
+    /// group instantiations by generic base name, print bases with at least N
+    #[argh(option)]
+    min_instances: Option<usize>,
+
+    /// print how many matched symbols fall into each size bucket instead of symbols
+    #[argh(switch)]
+    histogram: bool,
+
+    /// end with a `SUMMARY key=value ...` line instead of the total line
+    #[argh(switch)]
+    porcelain: bool,
+
+    /// print relocation count of each section instead of symbols
+    #[argh(switch)]
+    relocations: bool,
 }
 
 struct Filter<'a, 'data> {
     object: object::File<'data>,
     keywords: &'a [String],
     no_demangle: bool,
+    all: bool,
+    dynamic: bool,
+    all_kinds: bool,
+    generic: Option<String>,
+    swift: bool,
+    no_undefined: bool,
+    versions: HashMap<usize, &'data [u8]>,
 }
 
 impl<'a, 'data> Filter<'a, 'data> {
-    fn new(obj: object::File<'data>, keywords: &'a [String], no_demangle: bool)
-        -> Filter<'a, 'data>
-    {
-        Filter {
-            object: obj,
-            keywords, no_demangle
-        }
-    }
-
     fn for_each<F>(&self, mut f: F) -> anyhow::Result<()>
     where
         F: FnMut(&[u8], Symbol) -> anyhow::Result<()>
@@ -58,29 +115,50 @@ impl<'a, 'data> Filter<'a, 'data> {
         };
         let mut namebuf = Vec::new();
 
-        let symbol_table = self.object.symbol_table()
-            .context("not found symbol_table")?;
+        let symbol_table = symbol_table(&self.object, self.dynamic)?;
 
         for symbol in symbol_table.symbols() {
-            if symbol.kind() != SymbolKind::Text {
-                continue
+            match symbol.kind() {
+                SymbolKind::Text => (),
+                SymbolKind::Data | SymbolKind::Tls | SymbolKind::Label | SymbolKind::Unknown
+                    if self.all_kinds => (),
+                _ => continue
+            }
+
+            if self.no_undefined && symbol.is_undefined() {
+                continue;
             }
 
             if let Some(mangled_name) = symbol.name().ok().filter(|name| !name.is_empty()) {
-                write!(&mut namebuf, "{}", demangle(mangled_name))?;
+                write!(&mut namebuf, "{}", Demangle(mangled_name, self.swift))?;
                 let name = namebuf.as_bytes();
 
-                if ac.as_ref()
-                    .map(|ac| ac.is_match(&name))
-                    .unwrap_or(true)
-                    || self.keywords.iter().any(|w| mangled_name.ends_with(w))
-                {
-                    let name = if self.no_demangle {
-                        mangled_name.as_bytes()
-                    } else {
-                        name
-                    };
-                    f(name, symbol)?;
+                let is_match = if self.all {
+                    self.keywords.iter()
+                        .all(|w| name.contains_str(w) || mangled_name.ends_with(w))
+                } else {
+                    ac.as_ref()
+                        .map(|ac| ac.is_match(name))
+                        .unwrap_or(true)
+                        || self.keywords.iter().any(|w| mangled_name.ends_with(w))
+                };
+
+                let is_match = is_match && self.generic.as_ref()
+                    .map(|generic| name.contains_str(generic))
+                    .unwrap_or(true);
+
+                if is_match {
+                    if self.no_demangle {
+                        namebuf.clear();
+                        namebuf.extend_from_slice(mangled_name.as_bytes());
+                    }
+
+                    if let Some(version) = self.versions.get(&symbol.index().0) {
+                        namebuf.push(b'@');
+                        namebuf.extend_from_slice(version);
+                    }
+
+                    f(&namebuf, symbol)?;
                 }
 
                 namebuf.clear();
@@ -92,32 +170,229 @@ impl<'a, 'data> Filter<'a, 'data> {
 }
 
 impl Options {
-    pub fn exec(self) -> anyhow::Result<()> {
-        let Options { file, keywords, sort, no_demangle } = self;
+    /// Fill options that weren't given and turn on switches from `SYMBOL_TOOLS_*` environment variables
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.sort |= env_switch("SORT");
+        self.no_demangle |= env_switch("NO_DEMANGLE");
+        self.swift |= env_switch("SWIFT");
+        self.addr_width = self.addr_width.or(env_option("ADDR_WIDTH")?);
+
+        Ok(())
+    }
+
+    pub fn exec(self, mmap: bool, mut timings: Timings, stdout: &mut dyn Write) -> anyhow::Result<()> {
+        let Options {
+            file, mut keywords, keywords_file, sort, no_demangle, all, lang, dynamic, addr_width,
+            generic, swift, nm, no_undefined, versions, min_instances, histogram, porcelain,
+            relocations
+        } = self;
+
+        let addr_width = addr_width.map_or(16, |width| width.0);
 
-        let fd = fs::File::open(&file)?;
+        if let Some(path) = keywords_file {
+            let list = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read keywords: {}", path.display()))?;
 
-        let mmap = unsafe { Mmap::map(&fd)? };
-        let object = object::File::parse(mmap.as_ref())?;
+            keywords.extend(list.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+            );
+        }
+
+        let buf = load_file(&file, mmap)?;
+        let object = object::File::parse(&*buf)?;
+        timings.phase("load");
+
+        if relocations {
+            let mut total = 0;
+
+            for section in object.sections() {
+                // count without resolving each relocation
+                let count = section.relocations().count();
+
+                if count != 0 {
+                    total += count;
+                    writeln!(stdout, "{}: {} relocations", section.name().unwrap_or("<error>"), count)?;
+                }
+            }
+
+            if porcelain {
+                writeln!(stdout, "SUMMARY count={}", total)?;
+            } else {
+                writeln!(stdout, "total:\t\t\t{}", total)?;
+            }
+            timings.phase("scan");
+
+            return Ok(());
+        }
 
         if !object.has_debug_symbols() {
             eprintln!("WARN: The file is missing debug symbols.");
         }
 
-        let filter = Filter::new(object, &keywords, no_demangle);
+        let versions = if versions {
+            symbol_versions(&object, dynamic)?
+        } else {
+            HashMap::new()
+        };
+        let filter = Filter {
+            object,
+            keywords: &keywords,
+            no_demangle,
+            all,
+            dynamic,
+            all_kinds: nm,
+            generic: generic.map(|base| format!("{}<", base)),
+            swift,
+            no_undefined,
+            versions
+        };
 
         let mut count = 0;
-        let stdout = io::stdout();
-        let mut stdout = stdout.lock();
+        let mut rows = 0;
+        if nm {
+            let mut output = Vec::new();
+
+            filter.for_each(|name, symbol| {
+                let mut kind = symbol_kind(&filter.object, &symbol);
+
+                // nm marks weak symbols with their own letters
+                if symbol.is_weak() {
+                    kind = match (kind, symbol.kind()) {
+                        ('U', SymbolKind::Data) => 'v',
+                        ('U', _) => 'w',
+                        (_, SymbolKind::Data) => 'V',
+                        _ => 'W'
+                    };
+                }
+
+                output.push((Vec::from(name), kind, symbol.address(), symbol.is_undefined()));
+
+                Ok(())
+            })?;
+
+            output.sort();
+
+            for (name, kind, addr, undefined) in output.iter() {
+                if *undefined {
+                    writeln!(stdout, "{:1$} {2} {3}", "", addr_width, kind, name.as_bstr())?;
+                } else {
+                    writeln!(stdout, "{} {} {}", Address(*addr, addr_width), kind, name.as_bstr())?;
+                }
+            }
+
+            if porcelain {
+                writeln!(stdout, "SUMMARY count={}", output.len())?;
+            }
+            timings.phase("scan");
+
+            return Ok(());
+        }
+
+        if let Some(min_instances) = min_instances {
+            // base name -> (instances, size)
+            let mut groups: BTreeMap<Vec<u8>, (usize, u64)> = BTreeMap::new();
+
+            filter.for_each(|name, symbol| {
+                let entry = groups.entry(generic_base(name)).or_default();
+                entry.0 += 1;
+                entry.1 += symbol.size();
+
+                Ok(())
+            })?;
+
+            let mut output = groups.into_iter()
+                .filter(|(_, (instances, _))| *instances >= min_instances)
+                .collect::<Vec<_>>();
+
+            if sort {
+                output.sort_by_key(|(_, (_, size))| *size);
+            }
+
+            for (base, (instances, size)) in output.iter() {
+                count += size;
+
+                writeln!(stdout, "{}\t{}\t\t{}", instances, size, base.as_bstr())?;
+            }
+
+            if porcelain {
+                writeln!(stdout, "SUMMARY count={} total={}", output.len(), count)?;
+            } else {
+                writeln!(stdout, "total:\t{}", count)?;
+            }
+            timings.phase("scan");
+
+            return Ok(());
+        }
+
+        if histogram {
+            const BUCKETS: &[(u64, &str)] = &[
+                (0, "<64"),
+                (64, "64-256"),
+                (256, "256-1K"),
+                (1 << 10, "1K-4K"),
+                (4 << 10, "4K-16K"),
+                (16 << 10, "16K-64K"),
+                (64 << 10, ">=64K")
+            ];
+            const BAR_WIDTH: usize = 40;
+
+            // bucket -> (symbols, size)
+            let mut buckets = vec![(0, 0); BUCKETS.len()];
+
+            filter.for_each(|_, symbol| {
+                let size = symbol.size();
+                let idx = BUCKETS.iter().rposition(|&(start, _)| size >= start).unwrap_or(0);
+                buckets[idx].0 += 1;
+                buckets[idx].1 += size;
+
+                Ok(())
+            })?;
+
+            let max = buckets.iter().map(|&(n, _)| n).max().unwrap_or(0);
+
+            for (&(_, label), &(n, size)) in BUCKETS.iter().zip(buckets.iter()) {
+                // any non-empty bucket gets at least one mark
+                let bar = if n == 0 { 0 } else { (n * BAR_WIDTH / max).max(1) };
+
+                rows += n;
+                count += size;
+
+                writeln!(stdout, "{}\t{}\t{}\t{}", label, n, size, "#".repeat(bar))?;
+            }
+
+            if porcelain {
+                writeln!(stdout, "SUMMARY count={} total={}", rows, count)?;
+            } else {
+                writeln!(stdout, "total:\t{}\t{}", rows, count)?;
+            }
+            timings.phase("scan");
+
+            return Ok(());
+        }
+
+        let scheme_of = |symbol: &Symbol| if lang {
+            symbol.name().map(mangling_scheme).ok()
+        } else {
+            None
+        };
 
         if !sort {
             filter.for_each(|name, symbol| {
                 let size = symbol.size();
                 let addr = symbol.address();
+                let scheme = scheme_of(&symbol);
 
                 count += size;
+                rows += 1;
 
-                writeln!(&mut stdout, "{:018p}\t{}\t\t{}", addr as *const (), size, name.as_bstr())?;
+                writeln!(stdout, "{}\t{}\t{}\t{}",
+                    Address(addr, addr_width),
+                    size,
+                    SchemeColumn(scheme),
+                    name.as_bstr()
+                )?;
 
                 Ok(())
             })?;
@@ -125,22 +400,82 @@ impl Options {
             let mut output = Vec::new();
 
             filter.for_each(|name, symbol| {
-                output.push((symbol.address(), symbol.size(), Vec::from(name)));
+                output.push((symbol.address(), symbol.size(), scheme_of(&symbol), Vec::from(name)));
 
                 Ok(())
             })?;
 
-            output.sort_unstable_by_key(|symbol| symbol.1);
+            output.sort_unstable_by(|a, b| {
+                a.1.cmp(&b.1)
+                    .then_with(|| a.0.cmp(&b.0))
+                    .then_with(|| a.3.cmp(&b.3))
+            });
 
-            for (addr, size, name) in output {
+            for (addr, size, scheme, name) in output {
                 count += size;
+                rows += 1;
 
-                writeln!(&mut stdout, "{:018p}\t{}\t\t{}", addr as *const (), size, name.as_bstr())?;
+                writeln!(stdout, "{}\t{}\t{}\t{}",
+                    Address(addr, addr_width),
+                    size,
+                    SchemeColumn(scheme),
+                    name.as_bstr()
+                )?;
             }
         }
 
-        writeln!(&mut stdout, "total:\t\t\t{}", count)?;
+        if porcelain {
+            writeln!(stdout, "SUMMARY count={} total={}", rows, count)?;
+        } else {
+            writeln!(stdout, "total:\t\t\t{}", count)?;
+        }
+        timings.phase("scan");
 
         Ok(())
     }
 }
+
+/// Name with generic arguments collapsed to `<_>` and the legacy hash removed
+///
+/// Qualified paths like `<T as Trait>::f` keep their outer brackets, only
+/// argument lists that follow a path segment are collapsed.
+fn generic_base(name: &[u8]) -> Vec<u8> {
+    // legacy rust names end with `::h` and a 16 digit hash
+    let name = name.len().checked_sub(19)
+        .filter(|&n| name[n..].starts_with(b"::h"))
+        .map(|n| &name[..n])
+        .unwrap_or(name);
+
+    let mut base = Vec::with_capacity(name.len());
+    let mut iter = name.iter().enumerate();
+
+    while let Some((i, &b)) = iter.next() {
+        let is_args = b == b'<' && i > 0
+            && matches!(name[i - 1], b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'>');
+
+        if !is_args {
+            base.push(b);
+            continue
+        }
+
+        let mut depth = 1;
+        let mut prev = b;
+        for (_, &b) in iter.by_ref() {
+            match b {
+                b'<' => depth += 1,
+                // skip `->` of fn types
+                b'>' if prev != b'-' => depth -= 1,
+                _ => ()
+            }
+            prev = b;
+
+            if depth == 0 {
+                break
+            }
+        }
+
+        base.extend_from_slice(b"<_>");
+    }
+
+    base
+}
@@ -0,0 +1,550 @@
+//! Output of the subcommands against the objects in `tests/fixtures`
+//!
+//! `a.o`, `b.o` and `c.o` are built from their `.c` files with `cc -c -O1` for
+//! x86_64, `libab.a` holds `a.o` and `b.o`. `c.o` has three functions of the same
+//! size. `a-zstd.o` is `a.c` built with `-g`, then `objcopy --compress-debug-sections=zstd`.
+//! `d.o` is built with `-fcommon`, so it has common, bss and data symbols.
+//! `empty.o` is an empty file.
+//! `libv.so` is `v.c` linked with `cc -shared -nostdlib -Wl,--version-script=v.map`.
+//! `w.o` is built from `w.c` like `a.o`, with a weak `soft`, `w.nm` provides `soft` and a weak `hard`.
+//! `be.o` is `be.s` assembled with `llvm-mc -triple=armeb-linux-gnueabi`, a big endian object.
+//! `call.o` is `call.s` assembled with `llvm-mc -triple=armv7-none-eabi`, `caller` calls
+//! `callee`, tail calls `tail` and takes the address of `taken`.
+//! `fw.hex` is an Intel HEX file with two Thumb instructions at `0x8000000`.
+//! `pax.tar` is written by Python's `tarfile` in pax format, it holds `a.o` under a
+//! directory name longer than 100 bytes, `b.o` as `\xff.o` and a text file.
+
+use argh::FromArgs;
+use object::{ CompressionFormat, Object, ObjectSection };
+use crate::{ Options, run };
+use crate::link::{ parse_ihex, read_raw_binary, read_tar, run_script, wildcard_match };
+use crate::common::{ parse_nm_bsd, print_pretty_bytes, section_data, HexStr, Sha256 };
+
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn sym(args: &[&str]) -> String {
+    let options = Options::from_args(&["sym"], args)
+        .unwrap_or_else(|early_exit| panic!("bad args {:?}: {}", args, early_exit.output));
+    let mut output = Vec::new();
+
+    run(options, &mut output).unwrap();
+
+    String::from_utf8(output).unwrap()
+}
+
+fn sym_err(args: &[&str]) -> String {
+    let options = Options::from_args(&["sym"], args)
+        .unwrap_or_else(|early_exit| panic!("bad args {:?}: {}", args, early_exit.output));
+
+    match run(options, &mut Vec::new()) {
+        Ok(()) => panic!("expected {:?} to fail", args),
+        Err(err) => format!("{:#}", err)
+    }
+}
+
+#[test]
+fn test_search() {
+    let a = fixture("a.o");
+
+    assert_eq!(sym(&["search", &a]), "\
+        0000000000000000\t4\t\thelper\n\
+        0000000000000004\t5\t\tfoo\n\
+        0000000000000009\t18\t\tbar\n\
+        total:\t\t\t27\n");
+}
+
+#[test]
+fn test_search_keywords() {
+    let a = fixture("a.o");
+
+    assert_eq!(sym(&["search", &a, "foo", "bar"]), "\
+        0000000000000004\t5\t\tfoo\n\
+        0000000000000009\t18\t\tbar\n\
+        total:\t\t\t23\n");
+    assert_eq!(sym(&["search", &a, "o", "a", "--all"]), "total:\t\t\t0\n");
+    assert_eq!(sym(&["search", &a, "missing"]), "total:\t\t\t0\n");
+}
+
+#[test]
+fn test_search_sort() {
+    let a = fixture("a.o");
+
+    assert_eq!(sym(&["search", &a, "--sort", "--addr-width", "8"]), "\
+        00000000\t4\t\thelper\n\
+        00000004\t5\t\tfoo\n\
+        00000009\t18\t\tbar\n\
+        total:\t\t\t27\n");
+}
+
+#[test]
+fn test_search_sort_ties() {
+    let c = fixture("c.o");
+
+    // equal sizes fall back to address order
+    assert_eq!(sym(&["search", &c, "--sort"]), "\
+        0000000000000000\t6\t\tzeta\n\
+        0000000000000006\t6\t\talpha\n\
+        000000000000000c\t6\t\tmid\n\
+        total:\t\t\t18\n");
+}
+
+#[test]
+fn test_diff_by_content() {
+    let (a, b) = (fixture("a.o"), fixture("b.o"));
+
+    let output = sym(&["diff", &a, &b, "--by-content"]);
+    let rows = output.lines()
+        .map(|line| line.split('\t').collect::<Vec<_>>())
+        .filter(|row| row.len() == 5)
+        .map(|row| (row[2], row[4]))
+        .collect::<Vec<_>>();
+    assert_eq!(rows, [("removed", "bar"), ("added", "baz"), ("removed", "foo"), ("changed", "helper")]);
+    assert!(output.starts_with("85ff3bcc87a556d1f5d87407bba7a1cb352b1401550f38f2daab57f4d7bcafe8\t"));
+
+    assert_eq!(sym(&["diff", &a, &a, "--by-content"]), "changed:\t0\tadded:\t0\tremoved:\t0\n");
+}
+
+#[test]
+fn test_diff_sort() {
+    let (a, b) = (fixture("a.o"), fixture("b.o"));
+
+    assert_eq!(sym(&["diff", &a, &b, "--sort"]), "\
+        0000000000000009\t0000000000000000\t18\t0\t-18\t\tbar\n\
+        0000000000000004\t0000000000000000\t5\t0\t-5\t\tfoo\n\
+        0000000000000000\t0000000000000000\t4\t5\t1\t\thelper\n\
+        0000000000000000\t0000000000000005\t0\t5\t5\t\tbaz\n\
+        total:\t\t\t-17\n");
+}
+
+#[test]
+fn test_diff_oneway() {
+    let (a, b) = (fixture("a.o"), fixture("b.o"));
+
+    assert_eq!(sym(&["diff", &a, &b, "--sort", "--oneway"]), "\
+        0000000000000009\t0000000000000000\t18\t0\t-18\t\tbar\n\
+        0000000000000004\t0000000000000000\t5\t0\t-5\t\tfoo\n\
+        0000000000000000\t0000000000000000\t4\t5\t1\t\thelper\n\
+        total:\t\t\t-22\n");
+}
+
+#[test]
+fn test_diff_unsorted() {
+    let (a, b) = (fixture("a.o"), fixture("b.o"));
+
+    // rows come out in hash map order
+    let output = sym(&["diff", &a, &b]);
+    let mut lines = output.lines().collect::<Vec<_>>();
+    let total = lines.pop();
+    lines.sort();
+
+    assert_eq!(total, Some("total:\t\t\t-17"));
+    assert_eq!(lines, [
+        "0000000000000000\t0000000000000000\t4\t5\t1\t\thelper",
+        "0000000000000000\t0000000000000005\t0\t5\t5\t\tbaz",
+        "0000000000000004\t0000000000000000\t5\t0\t-5\t\tfoo",
+        "0000000000000009\t0000000000000000\t18\t0\t-18\t\tbar",
+    ]);
+}
+
+#[test]
+fn test_diff_sort_ties() {
+    let (b, c) = (fixture("b.o"), fixture("c.o"));
+
+    let output = sym(&["diff", &c, &b, "--sort"]);
+
+    assert_eq!(output, "\
+        0000000000000006\t0000000000000000\t6\t0\t-6\t\talpha\n\
+        000000000000000c\t0000000000000000\t6\t0\t-6\t\tmid\n\
+        0000000000000000\t0000000000000000\t6\t0\t-6\t\tzeta\n\
+        0000000000000000\t0000000000000005\t0\t5\t5\t\tbaz\n\
+        0000000000000000\t0000000000000000\t0\t5\t5\t\thelper\n\
+        total:\t\t\t-8\n");
+
+    for _ in 0..8 {
+        assert_eq!(sym(&["diff", &c, &b, "--sort"]), output);
+    }
+}
+
+#[test]
+fn test_diff_low_memory() {
+    let (a, b, c) = (fixture("a.o"), fixture("b.o"), fixture("c.o"));
+
+    for (old, new) in [(&a, &b), (&b, &a), (&c, &b), (&a, &c)] {
+        for flags in [&[][..], &["--sort"], &["--sort", "--oneway"], &["--show-moves", "--porcelain"]] {
+            let args = [&[old.as_str(), new.as_str()][..], flags].concat();
+            let hashed = sym(&[&["diff"][..], &args].concat());
+            let merged = sym(&[&["diff", "--low-memory"][..], &args].concat());
+
+            if flags.contains(&"--sort") {
+                assert_eq!(merged, hashed, "{:?}", args);
+            } else {
+                // unsorted rows come out in name order instead of hash map order
+                let mut hashed = hashed.lines().collect::<Vec<_>>();
+                hashed.sort();
+                let mut merged = merged.lines().collect::<Vec<_>>();
+                merged.sort();
+                assert_eq!(merged, hashed, "{:?}", args);
+            }
+        }
+    }
+
+    assert_eq!(sym(&["diff", &a, &b, "--low-memory"]), "\
+        0000000000000009\t0000000000000000\t18\t0\t-18\t\tbar\n\
+        0000000000000000\t0000000000000005\t0\t5\t5\t\tbaz\n\
+        0000000000000004\t0000000000000000\t5\t0\t-5\t\tfoo\n\
+        0000000000000000\t0000000000000000\t4\t5\t1\t\thelper\n\
+        total:\t\t\t-17\n");
+}
+
+#[test]
+fn test_contains() {
+    let (ar, a, b) = (fixture("libab.a"), fixture("a.o"), fixture("b.o"));
+
+    assert_eq!(sym(&["contains", &ar, &a, "--sort-by", "name"]), "\
+        0000000000000009\t18\t\tbar\n\
+        0000000000000004\t5\t\tfoo\n\
+        0000000000000000\t4\t\thelper\n\
+        total:\t\t\t27\n");
+    // equal sizes fall back to the name
+    assert_eq!(sym(&["contains", &ar, &b, "--sort"]), "\
+        0000000000000005\t5\t\tbaz\n\
+        0000000000000000\t5\t\thelper\n\
+        total:\t\t\t10\n");
+}
+
+#[test]
+fn test_search_versions() {
+    let so = fixture("libv.so");
+
+    // the static table borrows versions from the dynamic one by name
+    for args in [&["search", &so, "vfoo", "--versions"][..], &["search", &so, "vfoo", "--versions", "--dynamic"]] {
+        assert_eq!(sym(args), "\
+            0000000000001000\t6\t\tvfoo@V1\n\
+            total:\t\t\t6\n", "{:?}", args);
+    }
+}
+
+#[test]
+fn test_contains_weak() {
+    let (nm, w) = (fixture("w.nm"), fixture("w.o"));
+
+    // weak definitions in the object are always checked,
+    // `--include-weak` only counts the archive side ones
+    assert_eq!(sym(&["contains", &nm, &w]), "\
+        0000000000000000\t6\t\tsoft\n\
+        total:\t\t\t6\n");
+    assert_eq!(sym(&["contains", &nm, &w, "--include-weak"]), "\
+        0000000000000000\t6\t\tsoft\n\
+        0000000000000006\t6\t\thard\n\
+        total:\t\t\t12\n");
+}
+
+#[test]
+fn test_search_nm() {
+    let d = fixture("d.o");
+
+    assert_eq!(sym(&["search", &d, "--nm", "--addr-width", "8"]), "\
+        00000000 T bump\n\
+        00000004 C counter\n\
+        00000000 D value\n\
+        00000000 b zeroed\n");
+}
+
+#[test]
+fn test_addr_width() {
+    let d = fixture("d.o");
+
+    assert!(Options::from_args(&["sym"], &["search", &d, "--addr-width", "12"]).is_err());
+
+    let mut out = Vec::new();
+    print_pretty_bytes(&mut out, 0x1000, b"abc", 8).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), format!("0x00001000: 61 62 63 {:40}abc\n", ""));
+}
+
+#[test]
+fn test_bloat() {
+    let a = fixture("a.o");
+
+    assert_eq!(sym(&["bloat", &a]), "\
+        100.00%\t27\t3\t\t[unknown]\n\
+        total:\t27\t3\n");
+    assert_eq!(sym(&["bloat", &a, "--n", "0"]), "\
+        ...\t27\t3\t\t(1 more crates)\n\
+        total:\t27\t3\n");
+}
+
+#[test]
+fn test_empty_input() {
+    let a = fixture("a.o");
+    let ar = fixture("libab.a");
+    let empty = fixture("empty.o");
+    let expected = format!("empty input file: {}", empty);
+
+    for args in [
+        &["search", &empty][..],
+        &["diff", &empty, &a],
+        &["diff", &a, &empty],
+        &["contains", &ar, &empty],
+        &["bloat", &empty],
+        &["link", &empty],
+        &["link", &a, "--from-archive", &empty],
+        &["link", &empty, "--raw-binary", "--arch", "x86_64"]
+    ] {
+        assert_eq!(sym_err(args), expected, "{:?}", args);
+    }
+}
+
+#[test]
+fn test_zstd_section() {
+    let buf = std::fs::read(fixture("a-zstd.o")).unwrap();
+    let obj = object::File::parse(&*buf).unwrap();
+    let section = obj.section_by_name(".debug_info").unwrap();
+
+    assert_eq!(section.compressed_file_range().unwrap().format, CompressionFormat::Zstandard);
+
+    let data = section_data(&section).unwrap();
+    assert_eq!(data.len(), 232);
+    // DWARF version 5
+    assert_eq!(&data[4..6], &[5, 0]);
+}
+
+#[test]
+fn test_link_section_sizes() {
+    let a = fixture("a-zstd.o");
+
+    for cmd in ["section --sizes", "section --sizes --uncompressed-sizes"] {
+        let output = run_script(&[std::path::Path::new(&a)], &[cmd]).unwrap();
+        let line = |name: &str| output.lines()
+            .find(|line| line.ends_with(name))
+            .unwrap_or_else(|| panic!("no {} in {}", name, output))
+            .split_whitespace()
+            .skip(1)
+            .take(3)
+            .collect::<Vec<_>>();
+
+        // section header size, compressed bytes on disk, decompressed size
+        assert_eq!(line(" .debug_info"), ["166", "142", "232"], "{}", cmd);
+        assert_eq!(line(" .text"), ["97", "97", "97"], "{}", cmd);
+    }
+}
+
+#[test]
+fn test_sha256() {
+    let digest = |chunks: &[&[u8]]| {
+        let mut hasher = Sha256::default();
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+        HexStr(&hasher.finish()).to_string()
+    };
+
+    assert_eq!(digest(&[]), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(digest(&[b"ab", b"c"]), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(digest(&[&[b'a'; 999], b"a"]), "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3");
+}
+
+#[test]
+fn test_link_hash() {
+    let d = fixture("d.o");
+    let d = std::path::Path::new(&d);
+
+    // common and bss symbols have no bytes to hash
+    assert_eq!(run_script(&[d], &["symbols d.o --hash"]).unwrap(), "\
+        0000000000000000 b 16       zeroed\t-\n\
+        0000000000000000 T 39       bump\t158cd2c8b58c22df6a751114641a6b1ae5c89751e207976639d6f2fe683de1b3\n\
+        0000000000000004 C 0        counter\t-\n\
+        0000000000000000 D 4        value\te8613f5a5bc9f9feeda32a8e7c80b69dd4878e47b6a91723fb15eb84236b6a2b\n\
+        symbols: 4\n");
+    assert_eq!(run_script(&[d], &["search counter zeroed --hash --sort name"]).unwrap(), "\
+        0000000000000004 C counter @ \"d.o\"\t-\n\
+        0000000000000000 b zeroed @ \"d.o\"\t-\n\
+        matched: 2 symbols, total size: 16\n");
+    assert!(run_script(&[d], &["hash zeroed"]).is_err());
+}
+
+#[test]
+fn test_link_big_endian() {
+    let be = fixture("be.o");
+    let output = run_script(&[std::path::Path::new(&be)], &[
+        "dump add_one",
+        "reloc table"
+    ]).unwrap();
+
+    // armeb, instructions and the REL addend in `.data` are big endian
+    assert_eq!(output, "\
+        0000000000000000 T 8 add_one @ be.o/.text\n\
+        0x0: add r0, r0, #1\n\
+        0x4: bx lr\n\
+        OFFSET           ADDEND               TYPE    KIND         ADDRESS          NAME\n\
+        0000000000000000 4                    symbol  Absolute     0000000000000000 add_one\n\
+        relocations: 1\n  \
+        Absolute     1\n");
+}
+
+#[test]
+fn test_link_callgraph() {
+    let call = fixture("call.o");
+
+    // `movw`/`movt` of `taken` is not a call
+    assert_eq!(run_script(&[std::path::Path::new(&call)], &["callgraph caller"]).unwrap(), "\
+        caller\n  \
+        callee\n  \
+        tail\n");
+}
+
+#[test]
+fn test_link_whatis_top() {
+    let dir = std::env::temp_dir().join(format!("sym-test-whatis-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (blob, elf) = (dir.join("nop.bin"), dir.join("nop.o"));
+
+    // `.text` ends right at the top of the address space
+    std::fs::write(&blob, [0x90; 16]).unwrap();
+    let data = read_raw_binary(&blob, "x86_64".parse().unwrap(), Some(0xffff_ffff_ffff_fff0)).unwrap();
+    std::fs::write(&elf, data).unwrap();
+
+    let err = run_script(&[&elf], &["whatis fffffffffffffff8 nop.o"]).unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(err.to_string(), "address out of range");
+}
+
+#[test]
+fn test_tar_pax() {
+    let members = read_tar(fixture("pax.tar").as_ref()).unwrap();
+    let members = members.iter()
+        .map(|(name, data)| (name.as_os_str().as_encoded_bytes().to_vec(), data.len()))
+        .collect::<Vec<_>>();
+    let long_name = format!("{}/a.o", "d".repeat(120));
+
+    assert_eq!(members, [
+        (long_name.into_bytes(), 1512),
+        (b"\xff.o".to_vec(), 1160)
+    ]);
+}
+
+#[test]
+fn test_wildcard_match() {
+    assert!(wildcard_match(b"lib*.a", b"libab.a"));
+    assert!(wildcard_match(b"?.o", b"a.o"));
+    assert!(wildcard_match(b"*", b""));
+    assert!(wildcard_match(b"a*b*c", b"aXbYbZc"));
+    assert!(!wildcard_match(b"a*b", b"aXbY"));
+    assert!(!wildcard_match(b"?", b""));
+
+    // used to backtrack exponentially
+    let name = [b'a'; 64];
+    assert!(!wildcard_match(&[&b"*a".repeat(32)[..], b"b"].concat(), &name));
+}
+
+#[test]
+fn test_raw_binary_ihex() {
+    let hex = fixture("fw.hex");
+
+    for (arch, machine, entry) in [
+        ("thumb", "Arm", 0x8000001),
+        ("arm", "Arm", 0x8000000),
+        ("x86_64", "X86_64", 0x8000000)
+    ] {
+        let elf = read_raw_binary(hex.as_ref(), arch.parse().unwrap(), None).unwrap();
+        let obj = object::File::parse(&*elf).unwrap();
+        let text = obj.section_by_name(".text").unwrap();
+
+        // 32-bit arches get an ELF32
+        assert_eq!(format!("{:?}", obj.architecture()), machine, "{}", arch);
+        assert_eq!(obj.is_64(), arch == "x86_64", "{}", arch);
+        assert_eq!(obj.entry(), entry, "{}", arch);
+        assert_eq!(text.address(), 0x8000000, "{}", arch);
+        assert_eq!(text.data().unwrap(), [0x00, 0x20, 0x70, 0x47], "{}", arch);
+    }
+
+    // far apart records become separate runs instead of one huge buffer
+    let runs = parse_ihex(b":0100000001FE\n:02000004FFFFFC\n:0100000002FD\n:00000001FF\n").unwrap();
+    assert_eq!(runs, [(0, vec![1]), (0xffff0000, vec![2])]);
+
+    let err = parse_ihex(b":020000040800F2\n:03000004080000F1\n").unwrap_err();
+    assert_eq!(err.to_string(), "bad extended address record length 3 at line 2");
+}
+
+#[test]
+fn test_nm_weak() {
+    let nm = "\
+                         w maybe
+        0000000000000000 W provided
+        0000000000000010 V object
+        0000000000000020 T strong
+    ";
+    let names = |include_weak| parse_nm_bsd(nm.as_bytes(), include_weak, false, false)
+        .unwrap()
+        .into_iter()
+        .map(|name| String::from_utf8(name).unwrap())
+        .collect::<Vec<_>>();
+
+    // a weak reference isn't a definition
+    assert_eq!(names(true), ["object", "provided", "strong"]);
+    assert_eq!(names(false), ["strong"]);
+}
+
+#[test]
+fn test_nm_parse() {
+    let cases: &[(&str, &str, bool, &[&str])] = &[
+        ("undefined has no address", "                 U printf\n0000000000000000 T main", false, &["main"]),
+        ("local text", "0000000000000000 t helper", false, &["helper"]),
+        ("data isn't text", "0000000000000000 D value\n0000000000000000 B zeroed\n0000000000000000 R table", false, &[]),
+        ("archive member header", "libab.a(a.o):\n0000000000000000 T foo\n\nb.o:\n0000000000000000 T baz", false, &["baz", "foo"]),
+        ("demangle", "0000000000000000 T _ZN3foo3barE", false, &["foo::bar"]),
+        ("weak reference", "                 w maybe", true, &[]),
+        ("weak definition", "0000000000000000 W provided", true, &["provided"]),
+        ("weak definition excluded", "0000000000000000 W provided", false, &[])
+    ];
+
+    for (what, nm, include_weak, expected) in cases {
+        let names = parse_nm_bsd(nm.as_bytes(), *include_weak, false, false)
+            .unwrap()
+            .into_iter()
+            .map(|name| String::from_utf8(name).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, *expected, "{}", what);
+    }
+}
+
+#[test]
+fn test_link_search_max() {
+    let paths = [fixture("a.o"), fixture("c.o"), fixture("d.o")];
+    let paths = paths.iter().map(std::path::Path::new).collect::<Vec<_>>();
+    let output = run_script(&paths, &["search e --max 2"]).unwrap();
+
+    // the first matches in symbol map order
+    assert_eq!(output, "\
+        0000000000000000 r table @ \"a.o\"\n\
+        0000000000000000 T helper @ \"a.o\"\n\
+        ... (truncated, 4 more)\n\
+        matched: 6 symbols, total size: 62\n");
+}
+
+#[test]
+fn test_link_load_unload() {
+    let c = fixture("c.o");
+    let ar = fixture("libab.a");
+    let output = run_script(&[std::path::Path::new(&c)], &[
+        &format!("load {}", ar),
+        "alias x b.o",
+        "search baz",
+        "unload b.o",
+        "alias",
+        "search baz",
+        "search helper"
+    ]).unwrap();
+
+    // the alias goes with the object
+    assert_eq!(output, "\
+        loaded 2 objects\n\
+        0000000000000005 T baz @ \"b.o\"\n\
+        matched: 1 symbols, total size: 5\n\
+        unloaded 1 objects\n\
+        matched: 0 symbols, total size: 0\n\
+        0000000000000000 T helper @ \"a.o\"\n\
+        matched: 1 symbols, total size: 4\n");
+}
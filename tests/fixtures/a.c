@@ -0,0 +1,4 @@
+int helper(int x) { return x * 3; }
+int foo(int x) { return helper(x) + 1; }
+static int table[8] = {1,2,3,4,5,6,7,8};
+int bar(int i) { return table[i] + foo(i); }
@@ -0,0 +1,2 @@
+int helper(int x) { return x * 5 + 2; }
+int baz(int x) { return helper(x) - 1; }
@@ -0,0 +1,15 @@
+	.arch	armv7-a
+	.text
+	.globl	add_one
+	.type	add_one, %function
+add_one:
+	add	r0, r0, #1
+	bx	lr
+	.size	add_one, .-add_one
+
+	.data
+	.globl	table
+	.type	table, %object
+table:
+	.word	add_one + 4
+	.size	table, 4
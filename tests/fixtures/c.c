@@ -0,0 +1,3 @@
+int zeta(void) { return 1; }
+int alpha(void) { return 2; }
+int mid(void) { return 3; }
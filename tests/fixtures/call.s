@@ -0,0 +1,28 @@
+	.arch	armv7-a
+	.text
+	.globl	callee
+	.type	callee, %function
+callee:
+	bx	lr
+	.size	callee, .-callee
+
+	.globl	tail
+	.type	tail, %function
+tail:
+	bx	lr
+	.size	tail, .-tail
+
+	.globl	taken
+	.type	taken, %function
+taken:
+	bx	lr
+	.size	taken, .-taken
+
+	.globl	caller
+	.type	caller, %function
+caller:
+	movw	r0, #:lower16:taken
+	movt	r0, #:upper16:taken
+	bl	callee
+	b	tail
+	.size	caller, .-caller
@@ -0,0 +1,4 @@
+int counter;
+static int zeroed[4];
+int value = 7;
+int bump(void) { return ++counter + ++zeroed[0] + value; }
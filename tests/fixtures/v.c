@@ -0,0 +1 @@
+int vfoo(void) { return 1; }
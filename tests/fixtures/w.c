@@ -0,0 +1,2 @@
+__attribute__((weak)) int soft(void) { return 1; }
+int hard(void) { return 2; }